@@ -0,0 +1,222 @@
+//! FLIC command line tool.
+
+extern crate flic;
+
+use std::env;
+use std::path::Path;
+use flic::{FlicFile,FlicHeaderEditor,FlicResult};
+use flic::convert::{FlicFormat,ResizeMode};
+use flic::edit::ReverseMode;
+
+const DEFAULT_MAX_FRAMES: usize = 64;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() <= 0 {
+        usage();
+        return;
+    }
+
+    let res = match args[0].as_str() {
+        "convert" => cmd_convert(&args[1..]),
+        "header" => cmd_header(&args[1..]),
+        "reverse" => cmd_reverse(&args[1..]),
+        _ => {
+            usage();
+            return;
+        },
+    };
+
+    if let Err(e) = res {
+        println!("Error occurred - {}", e);
+    }
+}
+
+fn usage() {
+    println!("Usage: flictool <command> [options]");
+    println!("");
+    println!("Commands:");
+    println!("  convert [--fli | --flc] [--letterbox <index>] <input> <output>");
+    println!("      Convert between FLI and FLC.  The format defaults to the output's");
+    println!("      extension.  FLI output is scaled to 320x200, or letterboxed.");
+    println!("  header [options] <file>");
+    println!("      Print the FLIC header, or patch it in place with the options:");
+    println!("      --speed-msec <n>, --speed-jiffies <n>, --aspect <x>:<y>,");
+    println!("      --creator <id>, --created <time>, --updater <id>, --updated <time>");
+    println!("  reverse [--pingpong] [--max-frames <n>] <input> <output>");
+    println!("      Write a FLIC that plays the input backwards.");
+}
+
+fn cmd_convert(args: &[String])
+        -> FlicResult<()> {
+    let mut format = None;
+    let mut resize = ResizeMode::Scale;
+    let mut filenames = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--fli" => format = Some(FlicFormat::Fli),
+            "--flc" => format = Some(FlicFormat::Flc),
+            "--letterbox" => {
+                match parse_next(&mut iter) {
+                    Some(c) => resize = ResizeMode::Letterbox(c),
+                    None => {
+                        usage();
+                        return Ok(());
+                    },
+                }
+            },
+            _ => filenames.push(arg),
+        }
+    }
+
+    if filenames.len() != 2 {
+        usage();
+        return Ok(());
+    }
+
+    let dst = Path::new(filenames[1]);
+    let format = format.unwrap_or_else(|| {
+        match dst.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("fli") => FlicFormat::Fli,
+            _ => FlicFormat::Flc,
+        }
+    });
+
+    println!("{} -> {}", filenames[0], filenames[1]);
+    flic::convert::convert(Path::new(filenames[0]), dst, format, resize)
+}
+
+fn cmd_header(args: &[String])
+        -> FlicResult<()> {
+    let mut speed_msec = None;
+    let mut speed_jiffies = None;
+    let mut aspect = None;
+    let mut creator = None;
+    let mut created = None;
+    let mut updater = None;
+    let mut updated = None;
+    let mut filenames = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let ok = match arg.as_str() {
+            "--speed-msec" => parse_next(&mut iter).map(|n| speed_msec = Some(n)),
+            "--speed-jiffies" => parse_next(&mut iter).map(|n| speed_jiffies = Some(n)),
+            "--creator" => parse_next(&mut iter).map(|n| creator = Some(n)),
+            "--created" => parse_next(&mut iter).map(|n| created = Some(n)),
+            "--updater" => parse_next(&mut iter).map(|n| updater = Some(n)),
+            "--updated" => parse_next(&mut iter).map(|n| updated = Some(n)),
+            "--aspect" => iter.next().and_then(|s| parse_aspect(s)).map(|xy| aspect = Some(xy)),
+            _ => {
+                filenames.push(arg);
+                Some(())
+            },
+        };
+
+        if ok.is_none() || filenames.len() > 1 {
+            usage();
+            return Ok(());
+        }
+    }
+
+    if filenames.len() != 1 {
+        usage();
+        return Ok(());
+    }
+
+    let filename = Path::new(filenames[0]);
+    let flic = FlicFile::open(filename)?;
+    let modified = speed_msec.is_some() || speed_jiffies.is_some() || aspect.is_some()
+            || creator.is_some() || created.is_some()
+            || updater.is_some() || updated.is_some();
+
+    if !modified {
+        println!("{}", filename.to_string_lossy());
+        println!("  format:   {}",
+                if flic.magic() == flic::flic::FLIH_MAGIC { "FLI" } else { "FLC" });
+        println!("  size:     {}x{}", flic.width(), flic.height());
+        println!("  frames:   {}", flic.frame_count());
+        println!("  speed:    {} msec, {} jiffies", flic.speed_msec(), flic.speed_jiffies());
+        println!("  aspect:   {}:{}", flic.aspect_x(), flic.aspect_y());
+        println!("  creator:  0x{:08X}, created {}", flic.creator(), flic.creation_time());
+        println!("  updater:  0x{:08X}, updated {}", flic.updater(), flic.update_time());
+        return Ok(());
+    }
+
+    let mut editor = FlicHeaderEditor::open(filename)?;
+    if let Some(n) = speed_msec {
+        editor.set_speed_msec(n);
+    }
+    if let Some(n) = speed_jiffies {
+        editor.set_speed_jiffies(n);
+    }
+    if let Some((x, y)) = aspect {
+        editor.set_aspect_ratio(x, y);
+    }
+    if creator.is_some() || created.is_some() {
+        editor.set_creator(
+                creator.unwrap_or(flic.creator()),
+                created.unwrap_or(flic.creation_time()));
+    }
+    if updater.is_some() || updated.is_some() {
+        editor.set_updater(
+                updater.unwrap_or(flic.updater()),
+                updated.unwrap_or(flic.update_time()));
+    }
+
+    editor.close()
+}
+
+fn cmd_reverse(args: &[String])
+        -> FlicResult<()> {
+    let mut mode = ReverseMode::Backward;
+    let mut max_frames = DEFAULT_MAX_FRAMES;
+    let mut filenames = Vec::new();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--pingpong" => mode = ReverseMode::PingPong,
+            "--max-frames" => {
+                match parse_next(&mut iter) {
+                    Some(n) => max_frames = n,
+                    None => {
+                        usage();
+                        return Ok(());
+                    },
+                }
+            },
+            _ => filenames.push(arg),
+        }
+    }
+
+    if filenames.len() != 2 {
+        usage();
+        return Ok(());
+    }
+
+    println!("{} -> {}", filenames[0], filenames[1]);
+    flic::edit::reverse(Path::new(filenames[0]), Path::new(filenames[1]),
+            mode, max_frames)
+}
+
+fn parse_next<'a, I, T>(iter: &mut I)
+        -> Option<T>
+        where I: Iterator<Item = &'a String>, T: ::std::str::FromStr {
+    iter.next().and_then(|s| s.parse().ok())
+}
+
+fn parse_aspect(s: &str)
+        -> Option<(u16, u16)> {
+    let mut xy = s.split(':');
+    match (xy.next(), xy.next(), xy.next()) {
+        (Some(x), Some(y), None) =>
+            match (x.parse(), y.parse()) {
+                (Ok(x), Ok(y)) => Some((x, y)),
+                _ => None,
+            },
+        _ => None,
+    }
+}
@@ -5,7 +5,8 @@ extern crate sdl2;
 
 use std::env;
 use std::path::PathBuf;
-use flic::{FlicFile,RasterMut};
+use flic::{FlicFile,Raster,RasterMut};
+use flic::render::{RenderFormat,render};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::PixelFormatEnum;
@@ -145,16 +146,8 @@ fn render_to_texture(
         texture: &mut sdl2::render::Texture,
         w: usize, h: usize, buf: &[u8], pal: &[u8]) {
     texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-        for y in 0..h {
-            for x in 0..w {
-                let offset = pitch * y + 3 * x;
-                let c = buf[w * y + x] as usize;
-
-                buffer[offset + 0] = pal[3 * c + 0];
-                buffer[offset + 1] = pal[3 * c + 1];
-                buffer[offset + 2] = pal[3 * c + 2];
-            }
-        }
+        let raster = Raster::new(w, h, buf, pal);
+        render(&raster, RenderFormat::Rgb24, None, buffer, pitch).unwrap();
     }).unwrap();
 }
 
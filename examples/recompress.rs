@@ -4,7 +4,7 @@ extern crate flic;
 
 use std::env;
 use std::path::{Path,PathBuf};
-use flic::{FlicFile,FlicFileWriter,FlicResult,Raster,RasterMut};
+use flic::{FlicFile,FlicFileWriter,FlicResult};
 
 fn main() {
     if env::args().count() <= 1 {
@@ -46,9 +46,11 @@ fn main() {
         println!("{} -> {}", filename, outname.to_string_lossy());
 
         match recompress(&mut fin, &mut fout) {
-            Ok(_) => {
-                let _ = fout.close();
-            },
+            Ok(_) =>
+                match fout.close() {
+                    Ok(summary) => println!("{}", summary),
+                    Err(e) => println!("Error occurred - {}", e),
+                },
             Err(e) => {
                 println!("Error occurred - {}", e);
             },
@@ -62,37 +64,26 @@ fn usage() {
 
 fn recompress(fin: &mut FlicFile, fout: &mut FlicFileWriter)
         -> FlicResult<()> {
-    let w = fin.width() as usize;
-    let h = fin.height() as usize;
-
     fout.set_creator(fin.creator(), fin.creation_time());
     fout.set_aspect_ratio(fin.aspect_x(), fin.aspect_y());
 
-    let mut buf_prev = vec![0; w * h];
-    let mut buf_next = vec![0; w * h];
-    let mut pal_prev = [0; 3 * 256];
-    let mut pal_next = [0; 3 * 256];
+    let mut prev = fin.new_frame();
+    let mut next = fin.new_frame();
     let mut first = true;
 
     loop {
-        let res = fin.read_next_frame(
-                &mut RasterMut::new(w, h, &mut buf_next, &mut pal_next))?;
+        let res = fin.read_next_frame_into(&mut next)?;
 
         if first {
-            fout.write_next_frame(
-                    None,
-                    &Raster::new(w, h, &buf_next, &pal_next))?;
+            fout.write_next_frame_from(None, &next)?;
         } else {
-            fout.write_next_frame(
-                    Some(&Raster::new(w, h, &buf_prev, &pal_prev)),
-                    &Raster::new(w, h, &buf_next, &pal_next))?;
+            fout.write_next_frame_from(Some(&prev), &next)?;
         }
 
         if res.looped {
             break;
         } else {
-            buf_prev.copy_from_slice(&buf_next);
-            pal_prev.copy_from_slice(&pal_next);
+            prev.copy_from_raster(&next.as_raster());
             first = false;
         }
     }
@@ -5,7 +5,7 @@ use byteorder::LittleEndian as LE;
 use byteorder::{ReadBytesExt,WriteBytesExt};
 
 use ::{FlicError,FlicResult,Raster,RasterMut};
-use super::{Group,GroupByEq};
+use super::{FrameChanges,Group,GroupByEq};
 
 /// Magic for a FLI_COLOR256 chunk - 256-Level Color.
 ///
@@ -36,6 +36,14 @@ pub const FLI_COLOR256: u16 = 4;
 /// Decode a FLI_COLOR256 chunk.
 pub fn decode_fli_color256(src: &[u8], dst: &mut RasterMut)
         -> FlicResult<()> {
+    decode_fli_color256_with_changes(src, dst, &mut FrameChanges::default())
+}
+
+/// Decode a FLI_COLOR256 chunk, adding the modified palette entries to
+/// `changes`.
+pub fn decode_fli_color256_with_changes(
+        src: &[u8], dst: &mut RasterMut, changes: &mut FrameChanges)
+        -> FlicResult<()> {
     let mut r = Cursor::new(src);
     let mut idx0 = 0;
 
@@ -54,6 +62,7 @@ pub fn decode_fli_color256(src: &[u8], dst: &mut RasterMut)
         }
 
         r.read_exact(&mut dst.pal[start..end])?;
+        changes.add_palette((start / 3)..(end / 3));
 
         idx0 = end;
     }
@@ -1,11 +1,12 @@
 //! Codec for chunk type 7 = FLI_SS2.
 
 use std::io::{Cursor,Read,Seek,SeekFrom,Write};
+use std::ops::Range;
 use byteorder::LittleEndian as LE;
 use byteorder::{ReadBytesExt,WriteBytesExt};
 
 use ::{FlicError,FlicResult,Raster,RasterMut};
-use super::{Group,GroupBySS2};
+use super::{FrameChanges,Group,GroupBySS2,Packet,check_spans,mask_row,optimal_packets,row_unchanged};
 
 /// Magic for a FLI_SS2 chunk - Word Aligned Delta Compression.
 ///
@@ -55,6 +56,13 @@ enum SS2Op {
 /// Decode a FLI_SS2 chunk.
 pub fn decode_fli_ss2(src: &[u8], dst: &mut RasterMut)
         -> FlicResult<()> {
+    decode_fli_ss2_with_changes(src, dst, &mut FrameChanges::default())
+}
+
+/// Decode a FLI_SS2 chunk, adding the modified pixels to `changes`.
+pub fn decode_fli_ss2_with_changes(
+        src: &[u8], dst: &mut RasterMut, changes: &mut FrameChanges)
+        -> FlicResult<()> {
     let mut r = Cursor::new(src);
     let mut y = 0;
 
@@ -71,6 +79,7 @@ pub fn decode_fli_ss2(src: &[u8], dst: &mut RasterMut)
                 // Write last byte.
                 let idx = dst.stride * (dst.y + y) + (dst.x + dst.w - 1);
                 dst.buf[idx] = count as u8;
+                changes.add_span(dst.w - 1, y, 1);
 
                 count = r.read_u16::<LE>()?;
                 if count == 0 {
@@ -98,6 +107,7 @@ pub fn decode_fli_ss2(src: &[u8], dst: &mut RasterMut)
                 }
 
                 r.read_exact(&mut row[start..end])?;
+                changes.add_span(start - dst.x, y, end - start);
 
                 x0 = end;
             } else {
@@ -113,6 +123,7 @@ pub fn decode_fli_ss2(src: &[u8], dst: &mut RasterMut)
                     e[0] = c0;
                     e[1] = c1;
                 }
+                changes.add_span(start - dst.x, y, end - start);
 
                 x0 = end;
             }
@@ -129,9 +140,38 @@ pub fn decode_fli_ss2(src: &[u8], dst: &mut RasterMut)
 pub fn encode_fli_ss2<W: Write + Seek>(
         prev: &Raster, next: &Raster, w: &mut W)
         -> FlicResult<usize> {
+    encode_ss2(prev, next, false, None, w)
+}
+
+/// Encode a FLI_SS2 chunk, choosing the packets that give the
+/// smallest possible chunk.  This is slower than `encode_fli_ss2`.
+pub fn encode_fli_ss2_optimal<W: Write + Seek>(
+        prev: &Raster, next: &Raster, w: &mut W)
+        -> FlicResult<usize> {
+    encode_ss2(prev, next, true, None, w)
+}
+
+/// Encode a FLI_SS2 chunk, only comparing the pixels in the spans of
+/// each row given by `changed_spans`, as returned by
+/// `ChangeHint::changed_spans`.  The other pixels are assumed to be
+/// unchanged.
+pub fn encode_fli_ss2_hinted<W: Write + Seek>(
+        prev: &Raster, next: &Raster, changed_spans: &[Vec<Range<usize>>], optimal: bool,
+        w: &mut W)
+        -> FlicResult<usize> {
+    encode_ss2(prev, next, optimal, Some(changed_spans), w)
+}
+
+fn encode_ss2<W: Write + Seek>(
+        prev: &Raster, next: &Raster, optimal: bool,
+        changed_spans: Option<&[Vec<Range<usize>>]>, w: &mut W)
+        -> FlicResult<usize> {
     if (prev.w != next.w) || (prev.h != next.h) {
         return Err(FlicError::WrongResolution);
     }
+    if let Some(spans) = changed_spans {
+        check_spans(spans, next.w, next.h)?;
+    }
 
     // Reserve space for line_count.
     let max_size = (next.w * next.h) as u64;
@@ -145,13 +185,15 @@ pub fn encode_fli_ss2<W: Write + Seek>(
 
     let mut line_count = 0;
     let mut skip_count = 0;
+    let mut masked = Vec::new();
 
-    for (p, n) in prev.buf[prev_start..prev_end].chunks(prev.stride)
-            .zip(next.buf[next_start..next_end].chunks(next.stride)) {
+    for (y, (p, n)) in prev.buf[prev_start..prev_end].chunks(prev.stride)
+            .zip(next.buf[next_start..next_end].chunks(next.stride))
+            .enumerate() {
         let p = &p[prev.x..(prev.x + prev.w)];
         let n = &n[next.x..(next.x + next.w)];
 
-        if &p[..] == &n[..] {
+        if row_unchanged(changed_spans, y, p, n) {
             skip_count = skip_count + 1;
             continue;
         }
@@ -172,51 +214,41 @@ pub fn encode_fli_ss2<W: Write + Seek>(
             skip_count = 0;
         }
 
-        let mut gs = GroupBySS2::new_ss2(p, n)
-                .set_prepend_same_run()
-                .set_ignore_final_same_run();
-        let mut packets: Vec<SS2Op> = Vec::new();
-        let mut state = SS2Op::Skip(0);
-        let mut count = 0;
-
-        while let Some(g) = gs.next() {
-            if let Some(new_state) = combine_packets(state, g) {
-                state = new_state;
-
-                // If an odd skip length is combined into a memcpy
-                // operation, force the memcpy length to be even.
-                if let SS2Op::Memcpy(start, n) = new_state {
-                    if n % 2 == 1 {
-                        state = SS2Op::Memcpy(start, n + 1);
-                        gs.idx = gs.idx + 1;
-                    }
-                }
-            } else {
-                let new_state = convert_packet(g);
-
-                packets.push(state);
-
-                // Insert Skip(0) between Memcpy and Memset operations.
-                match (state, new_state) {
-                    (SS2Op::Skip(_), _) => {},
-                    (_, SS2Op::Skip(_)) => {},
-                    (_, SS2Op::SetEnd(_)) => {},
-                    _ => packets.push(SS2Op::Skip(0)),
-                }
-
-                state = new_state
-            }
-        }
+        // Only look for changes inside the hinted spans.  The masked
+        // row runs to the end of the row, as the last byte may be set
+        // separately.
+        let (x0, pp, nn) = match changed_spans {
+            Some(spans) => {
+                let x0 = mask_row(p, n, &spans[y], 2, true, &mut masked);
+                (x0, &masked[..], &n[x0..])
+            },
+            None => (0, p, n),
+        };
+
+        let (packets, set_end) = if optimal {
+            let (packets, set_end) = optimal_packets(pp, nn, 2, true, 0);
+            let packets = packets.into_iter()
+                .map(|op| match op {
+                    Packet::Skip(len) => SS2Op::Skip(len),
+                    Packet::Memset(idx, len) => SS2Op::Memset(idx, len),
+                    Packet::Memcpy(idx, len) => SS2Op::Memcpy(idx, len),
+                })
+                .collect();
+            (packets, if set_end { Some(nn.len() - 1) } else { None })
+        } else {
+            make_packets(pp, nn)
+        };
+        let packets = shift_packets(packets, x0);
+        let set_end = set_end.map(|idx| idx + x0);
 
-        if let SS2Op::Skip(_) = state {
-        } else if let SS2Op::SetEnd(idx) = state {
+        if let Some(idx) = set_end {
             // Note: this must be followed by a packet count word.
             w.write_u8(n[idx])?; // low byte
             w.write_u8(0b1000_0000)?; // high byte
-        } else {
-            packets.push(state);
         }
 
+        let mut count = 0;
+
         // Reserve space for count.
         let pos1 = w.seek(SeekFrom::Current(0))?;
         w.write_i16::<LE>(0)?;
@@ -252,6 +284,72 @@ pub fn encode_fli_ss2<W: Write + Seek>(
     Ok((pos1 - pos0) as usize)
 }
 
+/// Move the packets x pixels to the right.  The packets always start
+/// with a skip, which is lengthened.
+fn shift_packets(packets: Vec<SS2Op>, x: usize)
+        -> Vec<SS2Op> {
+    packets.into_iter()
+        .enumerate()
+        .map(|(i, op)| match op {
+            SS2Op::Skip(len) => SS2Op::Skip(if i == 0 { len + x } else { len }),
+            SS2Op::Memset(idx, len) => SS2Op::Memset(idx + x, len),
+            SS2Op::Memcpy(idx, len) => SS2Op::Memcpy(idx + x, len),
+            SS2Op::SetEnd(idx) => SS2Op::SetEnd(idx + x),
+        })
+        .collect()
+}
+
+/// Group the line into packets greedily.
+///
+/// Returns the packets, and the index of the last byte if it is to be
+/// set separately.
+fn make_packets(p: &[u8], n: &[u8])
+        -> (Vec<SS2Op>, Option<usize>) {
+    let mut gs = GroupBySS2::new_ss2(p, n)
+            .set_prepend_same_run()
+            .set_ignore_final_same_run();
+    let mut packets: Vec<SS2Op> = Vec::new();
+    let mut state = SS2Op::Skip(0);
+
+    while let Some(g) = gs.next() {
+        if let Some(new_state) = combine_packets(state, g) {
+            state = new_state;
+
+            // If an odd skip length is combined into a memcpy
+            // operation, force the memcpy length to be even.
+            if let SS2Op::Memcpy(start, n) = new_state {
+                if n % 2 == 1 {
+                    state = SS2Op::Memcpy(start, n + 1);
+                    gs.idx = gs.idx + 1;
+                }
+            }
+        } else {
+            let new_state = convert_packet(g);
+
+            packets.push(state);
+
+            // Insert Skip(0) between Memcpy and Memset operations.
+            match (state, new_state) {
+                (SS2Op::Skip(_), _) => {},
+                (_, SS2Op::Skip(_)) => {},
+                (_, SS2Op::SetEnd(_)) => {},
+                _ => packets.push(SS2Op::Skip(0)),
+            }
+
+            state = new_state
+        }
+    }
+
+    match state {
+        SS2Op::Skip(_) => (packets, None),
+        SS2Op::SetEnd(idx) => (packets, Some(idx)),
+        _ => {
+            packets.push(state);
+            (packets, None)
+        },
+    }
+}
+
 fn combine_packets(s0: SS2Op, s1: Group)
         -> Option<SS2Op> {
     match (s0, s1) {
@@ -398,7 +496,8 @@ fn write_packet<W: Write>(
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use ::{Raster,RasterMut};
+    use ::{Raster,RasterMut,Rect};
+    use ::codec::ChangeHint;
     use super::*;
 
     #[test]
@@ -520,4 +619,93 @@ mod tests {
         let res = encode_fli_ss2(&prev, &next, &mut enc);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_encode_fli_ss2_hinted_spans() {
+        const SCREEN_W: usize = 31;
+        const SCREEN_H: usize = 4;
+        let pal = [0; 3 * 256];
+        let buf1 = [0; SCREEN_W * SCREEN_H];
+        let buf2: Vec<u8> = (0..(SCREEN_W * SCREEN_H))
+            .map(|i| if i / SCREEN_W == 2 { (i % 7) as u8 + 1 } else { 0 })
+            .collect();
+
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+        let rows = [ false, false, true, false ];
+        let rects = [ Rect { x: 5, y: 2, w: 3, h: 1 }, Rect { x: 29, y: 2, w: 2, h: 1 } ];
+        let row_spans = ChangeHint::Rows(&rows).changed_spans(SCREEN_W, SCREEN_H).expect("spans");
+        let rect_spans = ChangeHint::Rects(&rects).changed_spans(SCREEN_W, SCREEN_H).expect("spans");
+
+        for &optimal in &[ false, true ] {
+            let mut enc1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            let mut enc2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            let size1 = encode_fli_ss2_hinted(&prev, &next, &row_spans, optimal, &mut enc1)
+                .expect("encode");
+            let size2 = encode_fli_ss2_hinted(&prev, &next, &rect_spans, optimal, &mut enc2)
+                .expect("encode");
+            assert!(size2 < size1);
+
+            // The pixels inside the rectangles are updated.  Pixels
+            // sharing a word with them may be too.
+            let mut buf = buf1.to_vec();
+            let mut pal = [0; 3 * 256];
+            decode_fli_ss2(&enc2.get_ref()[..],
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("decode");
+            for (i, (&a, &b)) in buf.iter().zip(buf2.iter()).enumerate() {
+                let x = i % SCREEN_W;
+                let inside = i / SCREEN_W == 2 && ((5 <= x && x < 8) || 29 <= x);
+                let near = i / SCREEN_W == 2 && ((4 <= x && x < 9) || 28 <= x);
+                if inside {
+                    assert_eq!(a, b);
+                } else if !near {
+                    assert_eq!(a, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_fli_ss2_optimal() {
+        let buf1 = [ 1, 0, 1, 2, 2, 1, 2, 1, 0, 2, 1, 0 ];
+        let buf2 = [ 1, 1, 1, 1, 2, 1, 2, 1, 0, 2, 1, 2 ];
+
+        let expected_fast = [
+            0x01, 0x00, // hh 1
+            0x02, 0x80, // bit15 = 1, bit14 = 0, data = 0x02
+            0x01, 0x00, // count 1
+            1, 2,       // skip 1, length 2
+            0x01, 0x01, 0x01, 0x02 ];
+
+        let expected_optimal = [
+            0x01, 0x00, // hh 1
+            0x02, 0x80, // bit15 = 1, bit14 = 0, data = 0x02
+            0x01, 0x00, // count 1
+            0, (-2i8) as u8,    // skip 0, length -2
+            0x01, 0x01 ];
+
+        const SCREEN_W: usize = 12;
+        const SCREEN_H: usize = 1;
+        let pal = [0; 3 * 256];
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+
+        let mut enc: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let res = encode_fli_ss2(&prev, &next, &mut enc);
+        assert!(res.is_ok());
+        assert_eq!(&enc.get_ref()[..], &expected_fast[..]);
+
+        let mut enc: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let res = encode_fli_ss2_optimal(&prev, &next, &mut enc);
+        assert!(res.is_ok());
+        assert_eq!(&enc.get_ref()[..], &expected_optimal[..]);
+
+        let mut buf3 = buf1.clone();
+        let mut pal3 = [0; 3 * 256];
+        decode_fli_ss2(&enc.get_ref()[..],
+                &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf3, &mut pal3))
+                .expect("decode");
+        assert_eq!(&buf3[..], &buf2[..]);
+    }
 }
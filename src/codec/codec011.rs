@@ -5,7 +5,7 @@ use byteorder::LittleEndian as LE;
 use byteorder::{ReadBytesExt,WriteBytesExt};
 
 use ::{FlicError,FlicResult,Raster,RasterMut};
-use super::{Group,GroupByEq};
+use super::{FrameChanges,Group,GroupByEq};
 
 /// Magic for a FLI_COLOR64 chunk - 64-Level Color.
 ///
@@ -17,6 +17,14 @@ pub const FLI_COLOR64: u16 = 11;
 /// Decode a FLI_COLOR64 chunk.
 pub fn decode_fli_color64(src: &[u8], dst: &mut RasterMut)
         -> FlicResult<()> {
+    decode_fli_color64_with_changes(src, dst, &mut FrameChanges::default())
+}
+
+/// Decode a FLI_COLOR64 chunk, adding the modified palette entries to
+/// `changes`.
+pub fn decode_fli_color64_with_changes(
+        src: &[u8], dst: &mut RasterMut, changes: &mut FrameChanges)
+        -> FlicResult<()> {
     let mut r = Cursor::new(src);
     let mut idx0 = 0;
 
@@ -35,6 +43,7 @@ pub fn decode_fli_color64(src: &[u8], dst: &mut RasterMut)
         }
 
         r.read_exact(&mut dst.pal[start..end])?;
+        changes.add_palette((start / 3)..(end / 3));
         for i in start..end {
             if dst.pal[i] > ::std::u8::MAX / 4 {
                 return Err(FlicError::Corrupted);
@@ -1,11 +1,12 @@
 //! Codec for chunk type 12 = FLI_LC.
 
 use std::io::{Cursor,Read,Seek,SeekFrom,Write};
+use std::ops::Range;
 use byteorder::LittleEndian as LE;
 use byteorder::{ReadBytesExt,WriteBytesExt};
 
 use ::{FlicError,FlicResult,Raster,RasterMut};
-use super::{Group,GroupByLC};
+use super::{FrameChanges,Group,GroupByLC,Packet,check_spans,mask_row,optimal_packets,row_unchanged};
 
 /// Magic for a FLI_LC chunk - Byte Aligned Delta Compression.
 ///
@@ -51,13 +52,20 @@ enum LcOp {
 /// Decode a FLI_LC chunk.
 pub fn decode_fli_lc(src: &[u8], dst: &mut RasterMut)
         -> FlicResult<()> {
+    decode_fli_lc_with_changes(src, dst, &mut FrameChanges::default())
+}
+
+/// Decode a FLI_LC chunk, adding the modified pixels to `changes`.
+pub fn decode_fli_lc_with_changes(
+        src: &[u8], dst: &mut RasterMut, changes: &mut FrameChanges)
+        -> FlicResult<()> {
     let mut r = Cursor::new(src);
     let y0 = r.read_u16::<LE>()? as usize;
     let hh = r.read_u16::<LE>()? as usize;
 
     let start = dst.stride * (dst.y + y0);
     let end = dst.stride * (dst.y + y0 + hh);
-    for row in dst.buf[start..end].chunks_mut(dst.stride) {
+    for (y, row) in dst.buf[start..end].chunks_mut(dst.stride).enumerate() {
         let count = r.read_u8()?;
         let mut x0 = dst.x;
 
@@ -73,6 +81,7 @@ pub fn decode_fli_lc(src: &[u8], dst: &mut RasterMut)
                 }
 
                 r.read_exact(&mut row[start..end])?;
+                changes.add_span(start - dst.x, y0 + y, end - start);
 
                 x0 = end;
             } else {
@@ -86,6 +95,7 @@ pub fn decode_fli_lc(src: &[u8], dst: &mut RasterMut)
                 for e in &mut row[start..end] {
                     *e = c;
                 }
+                changes.add_span(start - dst.x, y0 + y, end - start);
 
                 x0 = end;
             }
@@ -99,9 +109,38 @@ pub fn decode_fli_lc(src: &[u8], dst: &mut RasterMut)
 pub fn encode_fli_lc<W: Write + Seek>(
         prev: &Raster, next: &Raster, w: &mut W)
         -> FlicResult<usize> {
+    encode_lc(prev, next, false, None, w)
+}
+
+/// Encode a FLI_LC chunk, choosing the packets that give the
+/// smallest possible chunk.  This is slower than `encode_fli_lc`.
+pub fn encode_fli_lc_optimal<W: Write + Seek>(
+        prev: &Raster, next: &Raster, w: &mut W)
+        -> FlicResult<usize> {
+    encode_lc(prev, next, true, None, w)
+}
+
+/// Encode a FLI_LC chunk, only comparing the pixels in the spans of
+/// each row given by `changed_spans`, as returned by
+/// `ChangeHint::changed_spans`.  The other pixels are assumed to be
+/// unchanged.
+pub fn encode_fli_lc_hinted<W: Write + Seek>(
+        prev: &Raster, next: &Raster, changed_spans: &[Vec<Range<usize>>], optimal: bool,
+        w: &mut W)
+        -> FlicResult<usize> {
+    encode_lc(prev, next, optimal, Some(changed_spans), w)
+}
+
+fn encode_lc<W: Write + Seek>(
+        prev: &Raster, next: &Raster, optimal: bool,
+        changed_spans: Option<&[Vec<Range<usize>>]>, w: &mut W)
+        -> FlicResult<usize> {
     if (prev.w != next.w) || (prev.h != next.h) {
         return Err(FlicError::WrongResolution);
     }
+    if let Some(spans) = changed_spans {
+        check_spans(spans, next.w, next.h)?;
+    }
 
     let prev_start = prev.stride * prev.y;
     let prev_end = prev.stride * (prev.y + prev.h);
@@ -110,7 +149,9 @@ pub fn encode_fli_lc<W: Write + Seek>(
 
     let y0 = prev.buf[prev_start..prev_end].chunks(prev.stride)
             .zip(next.buf[next_start..next_end].chunks(next.stride))
-            .take_while(|&(p, n)| &p[prev.x..(prev.x + prev.w)] == &n[next.x..(next.x + next.w)])
+            .enumerate()
+            .take_while(|&(y, (p, n))| row_unchanged(changed_spans, y,
+                    &p[prev.x..(prev.x + prev.w)], &n[next.x..(next.x + next.w)]))
             .count();
 
     if y0 >= next.h {
@@ -119,8 +160,10 @@ pub fn encode_fli_lc<W: Write + Seek>(
 
     let y1 = next.h - prev.buf[prev_start..prev_end].chunks(prev.stride)
             .zip(next.buf[next_start..next_end].chunks(next.stride))
+            .enumerate()
             .rev()
-            .take_while(|&(p, n)| &p[prev.x..(prev.x + prev.w)] == &n[next.x..(next.x + next.w)])
+            .take_while(|&(y, (p, n))| row_unchanged(changed_spans, y,
+                    &p[prev.x..(prev.x + prev.w)], &n[next.x..(next.x + next.w)]))
             .count();
 
     if y1 <= y0 {
@@ -142,49 +185,47 @@ pub fn encode_fli_lc<W: Write + Seek>(
     let prev_end = prev.stride * y1;
     let next_start = next.stride * y0;
     let next_end = next.stride * y1;
+    let mut masked = Vec::new();
 
-    for (p, n) in prev.buf[prev_start..prev_end].chunks(prev.stride)
-            .zip(next.buf[next_start..next_end].chunks(next.stride)) {
+    for (y, (p, n)) in prev.buf[prev_start..prev_end].chunks(prev.stride)
+            .zip(next.buf[next_start..next_end].chunks(next.stride))
+            .enumerate() {
         let p = &p[prev.x..(prev.x + prev.w)];
         let n = &n[next.x..(next.x + next.w)];
 
+        let spans = changed_spans.map(|spans| &spans[y0 + y][..]);
+        if spans.map_or(false, |spans| spans.is_empty()) {
+            w.write_u8(0)?;
+            continue;
+        }
+
+        // Only look for changes inside the hinted spans.
+        let (x0, pp, nn) = match spans {
+            Some(spans) => {
+                let x0 = mask_row(p, n, spans, 1, false, &mut masked);
+                (x0, &masked[..], &n[x0..(x0 + masked.len())])
+            },
+            None => (0, p, n),
+        };
+
+        let packets = if optimal {
+            make_optimal_packets(pp, nn, x0)
+        } else {
+            shift_packets(make_packets(pp, nn), x0)
+        };
+
         // Reserve space for count.
         let pos1 = w.seek(SeekFrom::Current(0))?;
         w.write_u8(0)?;
 
-        let mut state = LcOp::Skip(0);
         let mut count = 0;
-
-        for g in GroupByLC::new_lc(p, n)
-                .set_prepend_same_run()
-                .set_ignore_final_same_run() {
-            if let Some(new_state) = combine_packets(state, g) {
-                state = new_state;
-            } else {
-                let new_state = convert_packet(g);
-
-                count = write_packet(state, count, n, w)?;
-
-                // Insert Skip(0) between Memcpy and Memset operations.
-                match (state, new_state) {
-                    (LcOp::Skip(_), _) => {},
-                    (_, LcOp::Skip(_)) => {},
-                    _ => count = write_packet(LcOp::Skip(0), count, n, w)?,
-                }
-
-                if count > 2 * ::std::u8::MAX as usize {
-                    return Err(FlicError::ExceededLimit);
-                }
-
-                state = new_state;
+        for op in packets {
+            count = write_packet(op, count, n, w)?;
+            if count > 2 * ::std::u8::MAX as usize {
+                return Err(FlicError::ExceededLimit);
             }
         }
 
-        if let LcOp::Skip(_) = state {
-        } else {
-            count = write_packet(state, count, n, w)?;
-        }
-
         assert!(count % 2 == 0);
         if count > 2 * ::std::u8::MAX as usize {
             return Err(FlicError::ExceededLimit);
@@ -210,6 +251,94 @@ pub fn encode_fli_lc<W: Write + Seek>(
     Ok((pos1 - pos0) as usize)
 }
 
+/// Move the packets x pixels to the right.  The packets always start
+/// with a skip, which is lengthened.
+fn shift_packets(packets: Vec<LcOp>, x: usize)
+        -> Vec<LcOp> {
+    packets.into_iter()
+        .enumerate()
+        .map(|(i, op)| match op {
+            LcOp::Skip(len) => LcOp::Skip(if i == 0 { len + x } else { len }),
+            LcOp::Memset(idx, len) => LcOp::Memset(idx + x, len),
+            LcOp::Memcpy(idx, len) => LcOp::Memcpy(idx + x, len),
+        })
+        .collect()
+}
+
+/// Find the smallest packets for the line, moved x pixels to the
+/// right, that fit in the 8-bit packet count.  Each packet is made
+/// more expensive until they fit, or until the packet count can go
+/// no lower.
+fn make_optimal_packets(p: &[u8], n: &[u8], x: usize)
+        -> Vec<LcOp> {
+    let mut penalty = 0;
+    loop {
+        let packets = optimal_packets(p, n, 1, false, penalty).0.into_iter()
+            .map(|op| match op {
+                Packet::Skip(len) => LcOp::Skip(len),
+                Packet::Memset(idx, len) => LcOp::Memset(idx, len),
+                Packet::Memcpy(idx, len) => LcOp::Memcpy(idx, len),
+            })
+            .collect();
+        let packets = shift_packets(packets, x);
+
+        if count_packets(&packets) <= ::std::u8::MAX as usize || penalty > 2 * n.len() {
+            return packets;
+        }
+
+        penalty = 2 * penalty + 1;
+    }
+}
+
+/// The number of packets written for the operations, including
+/// the extra packets for long runs.
+fn count_packets(packets: &[LcOp])
+        -> usize {
+    let count: usize = packets.iter()
+        .map(|&op| match op {
+            LcOp::Skip(len) => 1 + 2 * (len.saturating_sub(1) / 255),
+            LcOp::Memset(_, len) => 1 + 2 * (len.saturating_sub(1) / 128),
+            LcOp::Memcpy(_, len) => 1 + 2 * (len.saturating_sub(1) / 127),
+        })
+        .sum();
+    count / 2
+}
+
+/// Group the line into packets greedily.
+fn make_packets(p: &[u8], n: &[u8])
+        -> Vec<LcOp> {
+    let mut packets = Vec::new();
+    let mut state = LcOp::Skip(0);
+
+    for g in GroupByLC::new_lc(p, n)
+            .set_prepend_same_run()
+            .set_ignore_final_same_run() {
+        if let Some(new_state) = combine_packets(state, g) {
+            state = new_state;
+        } else {
+            let new_state = convert_packet(g);
+
+            packets.push(state);
+
+            // Insert Skip(0) between Memcpy and Memset operations.
+            match (state, new_state) {
+                (LcOp::Skip(_), _) => {},
+                (_, LcOp::Skip(_)) => {},
+                _ => packets.push(LcOp::Skip(0)),
+            }
+
+            state = new_state;
+        }
+    }
+
+    if let LcOp::Skip(_) = state {
+    } else {
+        packets.push(state);
+    }
+
+    packets
+}
+
 fn combine_packets(s0: LcOp, s1: Group)
         -> Option<LcOp> {
     match (s0, s1) {
@@ -335,7 +464,8 @@ fn write_packet<W: Write>(
 #[cfg(test)]
 mod tests {
     use std::io::Cursor;
-    use ::{Raster,RasterMut};
+    use ::{Raster,RasterMut,Rect};
+    use ::codec::ChangeHint;
     use super::*;
 
     #[test]
@@ -410,4 +540,156 @@ mod tests {
         assert!(res.is_ok());
         assert_eq!(&enc.get_ref()[..], &expected[..]);
     }
+
+    #[test]
+    fn test_encode_fli_lc_hinted() {
+        const SCREEN_W: usize = 32;
+        const SCREEN_H: usize = 8;
+        let pal = [0; 3 * 256];
+        let buf1 = [0; SCREEN_W * SCREEN_H];
+        let buf2: Vec<u8> = (0..(SCREEN_W * SCREEN_H))
+            .map(|i| if i / SCREEN_W == 2 || i / SCREEN_W == 4 { (i % 5) as u8 } else { 0 })
+            .collect();
+
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+        let mut expected: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        encode_fli_lc(&prev, &next, &mut expected).expect("encode");
+
+        // Hints covering the changes give the same result.
+        let mut spans = vec![Vec::new(); SCREEN_H];
+        spans[2] = vec![0..SCREEN_W];
+        spans[4] = vec![0..SCREEN_W];
+        spans[5] = vec![0..SCREEN_W];
+        let mut enc: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let res = encode_fli_lc_hinted(&prev, &next, &spans, false, &mut enc);
+        assert!(res.is_ok());
+        assert_eq!(&enc.get_ref()[..], &expected.get_ref()[..]);
+
+        // Rows outside the hints are skipped.
+        spans[4] = Vec::new();
+        let mut enc: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let res = encode_fli_lc_hinted(&prev, &next, &spans, false, &mut enc);
+        assert!(res.is_ok());
+
+        let mut buf = buf1.to_vec();
+        let mut pal = [0; 3 * 256];
+        decode_fli_lc(&enc.get_ref()[..],
+                &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                .expect("decode");
+        for (i, (&a, &b)) in buf.iter().zip(buf2.iter()).enumerate() {
+            assert_eq!(a, if i / SCREEN_W == 2 { b } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_encode_fli_lc_hinted_spans() {
+        const SCREEN_W: usize = 32;
+        const SCREEN_H: usize = 4;
+        let pal = [0; 3 * 256];
+        let buf1 = [0; SCREEN_W * SCREEN_H];
+        let buf2: Vec<u8> = (0..(SCREEN_W * SCREEN_H))
+            .map(|i| if i / SCREEN_W == 1 { (i % 7) as u8 + 1 } else { 0 })
+            .collect();
+
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+        let rows = [ false, true, false, false ];
+        let rects = [ Rect { x: 5, y: 1, w: 3, h: 1 }, Rect { x: 20, y: 1, w: 2, h: 1 } ];
+        let row_spans = ChangeHint::Rows(&rows).changed_spans(SCREEN_W, SCREEN_H).expect("spans");
+        let rect_spans = ChangeHint::Rects(&rects).changed_spans(SCREEN_W, SCREEN_H).expect("spans");
+
+        for &optimal in &[ false, true ] {
+            let mut enc1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            let mut enc2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+            let size1 = encode_fli_lc_hinted(&prev, &next, &row_spans, optimal, &mut enc1)
+                .expect("encode");
+            let size2 = encode_fli_lc_hinted(&prev, &next, &rect_spans, optimal, &mut enc2)
+                .expect("encode");
+            assert!(size2 < size1);
+
+            // Only the pixels inside the rectangles are updated.
+            let mut buf = buf1.to_vec();
+            let mut pal = [0; 3 * 256];
+            decode_fli_lc(&enc2.get_ref()[..],
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("decode");
+            for (i, (&a, &b)) in buf.iter().zip(buf2.iter()).enumerate() {
+                let x = i % SCREEN_W;
+                let inside = i / SCREEN_W == 1 && ((5 <= x && x < 8) || (20 <= x && x < 22));
+                assert_eq!(a, if inside { b } else { 0 });
+            }
+        }
+
+        // Spans must be sorted, disjoint and within the row.
+        let mut bad = row_spans.clone();
+        bad[1] = vec![ 4..8, 2..3 ];
+        let mut enc: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        assert!(encode_fli_lc_hinted(&prev, &next, &bad, false, &mut enc).is_err());
+        bad[1] = vec![ 4..(SCREEN_W + 1) ];
+        assert!(encode_fli_lc_hinted(&prev, &next, &bad, false, &mut enc).is_err());
+    }
+
+    #[test]
+    fn test_encode_fli_lc_optimal() {
+        const SCREEN_W: usize = 63;
+        const SCREEN_H: usize = 16;
+        let pal = [0; 3 * 256];
+
+        // Pseudo-random sparse changes, with runs.
+        let buf1: Vec<u8> = (0..(SCREEN_W * SCREEN_H))
+            .map(|i| ((i * 7919) % 13) as u8)
+            .collect();
+        let buf2: Vec<u8> = buf1.iter().enumerate()
+            .map(|(i, &c)| match (i * 104729) % 17 {
+                0 | 1 => ((i / 5) % 3) as u8,
+                2 => 0xAB,
+                _ => c,
+            })
+            .collect();
+
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+
+        let mut enc1: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let size1 = encode_fli_lc(&prev, &next, &mut enc1).expect("encode");
+        let mut enc2: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        let size2 = encode_fli_lc_optimal(&prev, &next, &mut enc2).expect("encode");
+        assert!(size2 < size1);
+
+        let mut buf3 = buf1.clone();
+        let mut pal3 = [0; 3 * 256];
+        decode_fli_lc(&enc2.get_ref()[..],
+                &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf3, &mut pal3))
+                .expect("decode");
+        assert_eq!(&buf3[..], &buf2[..]);
+    }
+
+    #[test]
+    fn test_encode_fli_lc_optimal_packet_count() {
+        const SCREEN_W: usize = 1024;
+        const SCREEN_H: usize = 2;
+        let pal = [0; 3 * 256];
+
+        // One changed pixel in every four, which alone would take
+        // 256 packets on the first line.
+        let buf1 = [0; SCREEN_W * SCREEN_H];
+        let buf2: Vec<u8> = (0..(SCREEN_W * SCREEN_H))
+            .map(|i| if i < SCREEN_W && i % 4 == 0 { (i / 4) as u8 | 1 } else { 0 })
+            .collect();
+
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+
+        let mut enc: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+        encode_fli_lc_optimal(&prev, &next, &mut enc).expect("encode");
+        assert_eq!(&enc.get_ref()[0..4], &[ 0x00, 0x00, 0x01, 0x00 ]);
+
+        let mut buf3 = buf1.to_vec();
+        let mut pal3 = [0; 3 * 256];
+        decode_fli_lc(&enc.get_ref()[..],
+                &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf3, &mut pal3))
+                .expect("decode");
+        assert_eq!(&buf3[..], &buf2[..]);
+    }
 }
@@ -161,6 +161,140 @@ pub fn prepare_pstamp(
     pstamp
 }
 
+/// Create a new scaled down RGB image using nearest neighbour
+/// sampling.
+pub fn sample_pstamp_rgb(
+        src: &Raster, dst_w: usize, dst_h: usize)
+        -> Vec<u8> {
+    dst_w.checked_mul(dst_h).and_then(|n| n.checked_mul(3)).expect("overflow");
+
+    let mut rgb = vec![0; 3 * dst_w * dst_h];
+
+    for (sy, dy) in LinScale::new(src.h, dst_h) {
+        let src_start = src.stride * (src.y + sy) + src.x;
+        let src_end = src_start + src.w;
+        let dst_start = 3 * dst_w * dy;
+        let dst_end = dst_start + 3 * dst_w;
+        let src_row = &src.buf[src_start..src_end];
+        let dst_row = &mut rgb[dst_start..dst_end];
+
+        for (sx, dx) in LinScale::new(src.w, dst_w) {
+            let c = src_row[sx] as usize;
+            dst_row[(3 * dx)..(3 * dx + 3)].copy_from_slice(&src.pal[(3 * c)..(3 * c + 3)]);
+        }
+    }
+
+    rgb
+}
+
+/// Create a new scaled down RGB image, where each pixel is the
+/// average colour of the source pixels it covers.
+pub fn average_pstamp_rgb(
+        src: &Raster, dst_w: usize, dst_h: usize)
+        -> Vec<u8> {
+    dst_w.checked_mul(dst_h).and_then(|n| n.checked_mul(3)).expect("overflow");
+
+    let cols: Vec<(usize, usize)> = (0..dst_w)
+        .map(|dx| area_bounds(dx, src.w, dst_w))
+        .collect();
+
+    let mut rgb = vec![0; 3 * dst_w * dst_h];
+    let mut sums = vec![0u64; 3 * dst_w];
+
+    for dy in 0..dst_h {
+        let (y0, y1) = area_bounds(dy, src.h, dst_h);
+
+        for e in sums.iter_mut() {
+            *e = 0;
+        }
+
+        for sy in y0..y1 {
+            let src_start = src.stride * (src.y + sy) + src.x;
+            let src_end = src_start + src.w;
+            let src_row = &src.buf[src_start..src_end];
+
+            for (dx, &(x0, x1)) in cols.iter().enumerate() {
+                for &c in &src_row[x0..x1] {
+                    let c = c as usize;
+                    sums[3 * dx + 0] = sums[3 * dx + 0] + src.pal[3 * c + 0] as u64;
+                    sums[3 * dx + 1] = sums[3 * dx + 1] + src.pal[3 * c + 1] as u64;
+                    sums[3 * dx + 2] = sums[3 * dx + 2] + src.pal[3 * c + 2] as u64;
+                }
+            }
+        }
+
+        let dst_start = 3 * dst_w * dy;
+        for (dx, &(x0, x1)) in cols.iter().enumerate() {
+            let n = ((x1 - x0) * (y1 - y0)) as u64;
+            for i in 0..3 {
+                rgb[dst_start + 3 * dx + i] = ((sums[3 * dx + i] + n / 2) / n) as u8;
+            }
+        }
+    }
+
+    rgb
+}
+
+/// Map an RGB image into the postage stamp's six-cube palette,
+/// optionally with Floyd-Steinberg dithering.
+pub fn map_pstamp_rgb(
+        rgb: &[u8], dst_w: usize, dst_h: usize, dither: bool)
+        -> Vec<u8> {
+    assert_eq!(rgb.len(), 3 * dst_w * dst_h);
+
+    let mut pstamp = vec![0; dst_w * dst_h];
+
+    if !dither {
+        for (e, c) in pstamp.iter_mut().zip(rgb.chunks(3)) {
+            *e = (((6 * c[0] as u32) / 256) * 36
+                + ((6 * c[1] as u32) / 256) * 6
+                + ((6 * c[2] as u32) / 256)) as u8;
+        }
+        return pstamp;
+    }
+
+    // Errors are scaled by 16, with one pixel of padding either side.
+    let mut err_curr = vec![0i32; 3 * (dst_w + 2)];
+    let mut err_next = vec![0i32; 3 * (dst_w + 2)];
+
+    for y in 0..dst_h {
+        for x in 0..dst_w {
+            let mut idx = 0;
+
+            for i in 0..3 {
+                let v = rgb[3 * (dst_w * y + x) + i] as i32 + err_curr[3 * (x + 1) + i] / 16;
+                let v = ::std::cmp::max(0, ::std::cmp::min(v, 255));
+                let level = ::std::cmp::min((6 * v + 128) / 256, 5);
+                let e = v - (level * 256) / 6;
+
+                err_curr[3 * (x + 2) + i] = err_curr[3 * (x + 2) + i] + 7 * e;
+                err_next[3 * (x + 0) + i] = err_next[3 * (x + 0) + i] + 3 * e;
+                err_next[3 * (x + 1) + i] = err_next[3 * (x + 1) + i] + 5 * e;
+                err_next[3 * (x + 2) + i] = err_next[3 * (x + 2) + i] + 1 * e;
+
+                idx = 6 * idx + level;
+            }
+
+            pstamp[dst_w * y + x] = idx as u8;
+        }
+
+        ::std::mem::swap(&mut err_curr, &mut err_next);
+        for e in err_next.iter_mut() {
+            *e = 0;
+        }
+    }
+
+    pstamp
+}
+
+/// The range of source pixels covered by a destination pixel.
+fn area_bounds(d: usize, src_len: usize, dst_len: usize)
+        -> (usize, usize) {
+    let s0 = d * src_len / dst_len;
+    let s1 = (d + 1) * src_len / dst_len;
+    (s0, ::std::cmp::max(s1, s0 + 1))
+}
+
 #[cfg(test)]
 mod tests {
     use ::{Raster,RasterMut};
@@ -242,4 +376,45 @@ mod tests {
                 &raster, &xlat256, 4, 4);
         assert_eq!(&pstamp[..], &expected[..]);
     }
+
+    #[test]
+    fn test_average_pstamp_rgb() {
+        let src = [
+            0, 1, 2, 2,
+            1, 0, 2, 2 ];
+
+        let expected = [
+            0x80, 0x80, 0x80,   0x00, 0x00, 0xFF ];
+
+        let mut pal = [0; 3 * 256];
+        pal[3..6].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        pal[6..9].copy_from_slice(&[0x00, 0x00, 0xFF]);
+
+        let raster = Raster::new(4, 2, &src, &pal);
+        let rgb = average_pstamp_rgb(&raster, 2, 1);
+        assert_eq!(&rgb[..], &expected[..]);
+
+        // Nearest neighbour only sees the top left pixels.
+        let rgb = sample_pstamp_rgb(&raster, 2, 1);
+        assert_eq!(&rgb[..], &[0x00, 0x00, 0x00, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_map_pstamp_rgb() {
+        const W: usize = 8;
+        const H: usize = 8;
+        let rgb = [0x40; 3 * W * H];
+
+        let pstamp = map_pstamp_rgb(&rgb, W, H, false);
+        assert!(pstamp.iter().all(|&c| c == 43));
+
+        // Dithering mixes the two nearest grey levels, 42 and 85,
+        // keeping the average close to the source.
+        let pstamp = map_pstamp_rgb(&rgb, W, H, true);
+        assert!(pstamp.iter().all(|&c| c == 43 || c == 86));
+        assert!(pstamp.iter().any(|&c| c == 43) && pstamp.iter().any(|&c| c == 86));
+        let sum: u32 = pstamp.iter().map(|&c| (c as u32 / 43) * 256 / 6).sum();
+        let avg = sum / (W * H) as u32;
+        assert!(avg >= 0x3C && avg <= 0x44, "avg {}", avg);
+    }
 }
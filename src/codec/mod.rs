@@ -8,8 +8,9 @@ macro_rules! module {
 }
 
 use std::iter::Zip;
+use std::ops::Range;
 
-use ::{FlicError,FlicResult,RasterMut};
+use ::{FlicError,FlicResult,Raster,RasterMut,Rect};
 
 module!(codec001);
 module!(codec004);
@@ -75,6 +76,40 @@ struct GroupByValue<'a> {
     idx: usize,
 }
 
+/// A packet chosen by `optimal_packets`: a skip length, or a start
+/// index and length in bytes.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Packet {
+    Skip(usize),
+    Memset(usize, usize),
+    Memcpy(usize, usize),
+}
+
+/// Regions of a frame that may have changed since the previous
+/// frame, supplied by the caller to speed up delta encoding.  Pixels
+/// outside the regions are assumed to be unchanged.
+#[derive(Clone,Copy,Debug)]
+pub enum ChangeHint<'a> {
+    /// Changed rectangles, relative to the raster.
+    Rects(&'a [Rect]),
+
+    /// One flag per row of the raster, set if the row may have
+    /// changed.
+    Rows(&'a [bool]),
+}
+
+/// Region of the screen and range of the palette modified by
+/// decoding one or more chunks.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct FrameChanges {
+    /// Bounding rectangle of the modified pixels, relative to the
+    /// raster.
+    pub rect: Option<Rect>,
+
+    /// Range of modified palette indices.
+    pub palette: Option<Range<usize>>,
+}
+
 /// An iterator to help with linear scaling functions.
 struct LinScale {
     sw: usize,
@@ -92,20 +127,266 @@ pub fn chunk_modifies_palette(magic: u16)
     (magic == FLI_COLOR256) || (magic == FLI_COLOR64) || (magic == FLI_ICOLORS)
 }
 
+impl<'a> ChangeHint<'a> {
+    /// Expand the hint into the changed column spans of each row of a
+    /// w x h raster.  The spans of each row are sorted, and do not
+    /// overlap or touch.
+    pub fn changed_spans(&self, w: usize, h: usize)
+            -> FlicResult<Vec<Vec<Range<usize>>>> {
+        match *self {
+            ChangeHint::Rects(rects) => {
+                let mut spans = vec![Vec::new(); h];
+                for r in rects {
+                    let x1 = r.x.checked_add(r.w).ok_or(FlicError::BadInput)?;
+                    let y1 = r.y.checked_add(r.h).ok_or(FlicError::BadInput)?;
+                    if x1 > w || y1 > h {
+                        return Err(FlicError::BadInput);
+                    }
+                    if r.w > 0 {
+                        for row in spans[r.y..y1].iter_mut() {
+                            row.push(r.x..x1);
+                        }
+                    }
+                }
+
+                for row in spans.iter_mut() {
+                    merge_spans(row);
+                }
+                Ok(spans)
+            },
+
+            ChangeHint::Rows(rows) =>
+                if rows.len() == h {
+                    Ok(rows.iter()
+                        .map(|&changed| if changed && w > 0 { vec![0..w] } else { Vec::new() })
+                        .collect())
+                } else {
+                    Err(FlicError::BadInput)
+                },
+        }
+    }
+}
+
+/// Sort the spans and merge those that overlap or touch.
+fn merge_spans(spans: &mut Vec<Range<usize>>) {
+    spans.sort_by_key(|s| s.start);
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(spans.len());
+    for s in spans.drain(..) {
+        match merged.last_mut() {
+            Some(m) if s.start <= m.end => m.end = ::std::cmp::max(m.end, s.end),
+            _ => merged.push(s),
+        }
+    }
+
+    *spans = merged;
+}
+
+impl FrameChanges {
+    /// Returns true if nothing was modified.
+    pub fn is_empty(&self) -> bool {
+        self.rect.is_none() && self.palette.is_none()
+    }
+
+    /// Mark a span of w pixels starting at (x, y) as modified.
+    pub fn add_span(&mut self, x: usize, y: usize, w: usize) {
+        if w > 0 {
+            self.add_rect(Rect { x: x, y: y, w: w, h: 1 });
+        }
+    }
+
+    /// Mark a rectangle as modified.
+    pub fn add_rect(&mut self, r: Rect) {
+        if r.w <= 0 || r.h <= 0 {
+            return;
+        }
+
+        self.rect = Some(match self.rect {
+            Some(a) => {
+                let x0 = ::std::cmp::min(a.x, r.x);
+                let y0 = ::std::cmp::min(a.y, r.y);
+                let x1 = ::std::cmp::max(a.x + a.w, r.x + r.w);
+                let y1 = ::std::cmp::max(a.y + a.h, r.y + r.h);
+                Rect { x: x0, y: y0, w: x1 - x0, h: y1 - y0 }
+            },
+            None => r,
+        });
+    }
+
+    /// Mark a range of palette indices as modified.
+    pub fn add_palette(&mut self, range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        self.palette = Some(match self.palette.take() {
+            Some(p) =>
+                ::std::cmp::min(p.start, range.start)..::std::cmp::max(p.end, range.end),
+            None => range,
+        });
+    }
+
+    /// Merge in the changes from another set of changes.
+    pub fn merge(&mut self, other: &FrameChanges) {
+        if let Some(r) = other.rect {
+            self.add_rect(r);
+        }
+        if let Some(ref p) = other.palette {
+            self.add_palette(p.clone());
+        }
+    }
+}
+
+/// Check that every pixel outside the changed spans really is
+/// unchanged.
+pub fn verify_changed_spans(prev: &Raster, next: &Raster, changed_spans: &[Vec<Range<usize>>])
+        -> FlicResult<()> {
+    if (prev.w != next.w) || (prev.h != next.h) {
+        return Err(FlicError::WrongResolution);
+    }
+    check_spans(changed_spans, next.w, next.h)?;
+
+    let prev_start = prev.stride * prev.y;
+    let prev_end = prev.stride * (prev.y + prev.h);
+    let next_start = next.stride * next.y;
+    let next_end = next.stride * (next.y + next.h);
+
+    for ((p, n), spans) in prev.buf[prev_start..prev_end].chunks(prev.stride)
+            .zip(next.buf[next_start..next_end].chunks(next.stride))
+            .zip(changed_spans.iter()) {
+        let p = &p[prev.x..(prev.x + prev.w)];
+        let n = &n[next.x..(next.x + next.w)];
+
+        let mut x = 0;
+        for s in spans.iter().chain(Some(&(n.len()..n.len()))) {
+            if &p[x..s.start] != &n[x..s.start] {
+                return Err(FlicError::BadInput);
+            }
+            x = s.end;
+        }
+    }
+
+    Ok(())
+}
+
+/// Scale the source raster's pixels to fill the destination raster,
+/// using nearest neighbour sampling.  The palette is not copied.
+pub fn scale_raster(src: &Raster, dst: &mut RasterMut) {
+    for (sy, dy) in LinScale::new(src.h, dst.h) {
+        let src_start = src.stride * (src.y + sy) + src.x;
+        let src_row = &src.buf[src_start..(src_start + src.w)];
+        let dst_start = dst.stride * (dst.y + dy) + dst.x;
+        let dst_row = &mut dst.buf[dst_start..(dst_start + dst.w)];
+
+        for (sx, dx) in LinScale::new(src.w, dst.w) {
+            dst_row[dx] = src_row[sx];
+        }
+    }
+}
+
+/// Returns true if the row is unchanged, only comparing the changed
+/// spans if there is a hint.
+fn row_unchanged(changed_spans: Option<&[Vec<Range<usize>>]>, y: usize, p: &[u8], n: &[u8])
+        -> bool {
+    match changed_spans {
+        Some(spans) => spans[y].iter().all(|s| &p[s.clone()] == &n[s.clone()]),
+        None => p == n,
+    }
+}
+
+/// Check that there are changed spans for each row of a w x h
+/// raster, and that they are sorted, do not overlap, and lie within
+/// the row.
+fn check_spans(changed_spans: &[Vec<Range<usize>>], w: usize, h: usize)
+        -> FlicResult<()> {
+    if changed_spans.len() != h {
+        return Err(FlicError::BadInput);
+    }
+
+    for spans in changed_spans {
+        let mut x = 0;
+        for s in spans {
+            if s.start < x || s.start > s.end || s.end > w {
+                return Err(FlicError::BadInput);
+            }
+            x = s.end;
+        }
+    }
+
+    Ok(())
+}
+
+/// Prepare a hinted row for encoding.
+///
+/// Fills `masked` with the previous row's pixels over the columns
+/// from the first changed span, rounded down to a multiple of `unit`
+/// pixels, to the last changed span, or to the end of the row if
+/// `to_end` is set.  Pixels outside the spans are taken from the next
+/// row, so comparing `masked` against the next row only finds changes
+/// inside the spans.  Returns the first column.
+fn mask_row(p: &[u8], n: &[u8], spans: &[Range<usize>], unit: usize, to_end: bool,
+        masked: &mut Vec<u8>)
+        -> usize {
+    masked.clear();
+
+    let (x0, x1) = match (spans.first(), spans.last()) {
+        (Some(first), Some(last)) => {
+            let x0 = first.start - first.start % unit;
+            (x0, if to_end { n.len() } else { last.end })
+        },
+        _ => return 0,
+    };
+
+    masked.extend_from_slice(&n[x0..x1]);
+    for s in spans {
+        masked[(s.start - x0)..(s.end - x0)].copy_from_slice(&p[s.clone()]);
+    }
+
+    x0
+}
+
 /// Decode a chunk, based on the chunk type.
 pub fn decode_chunk(magic: u16, buf: &[u8], dst: &mut RasterMut)
         -> FlicResult<()> {
+    decode_chunk_with_changes(magic, buf, dst, &mut FrameChanges::default())
+}
+
+/// Decode a chunk, based on the chunk type, adding the modified
+/// pixels and palette entries to `changes`.
+pub fn decode_chunk_with_changes(
+        magic: u16, buf: &[u8], dst: &mut RasterMut, changes: &mut FrameChanges)
+        -> FlicResult<()> {
+    let screen = Rect { x: 0, y: 0, w: dst.w, h: dst.h };
+
     match magic {
-        FLI_WRUN => decode_fli_wrun(&buf, dst)?,
-        FLI_COLOR256 => decode_fli_color256(&buf, dst)?,
-        FLI_SS2 => decode_fli_ss2(&buf, dst)?,
-        FLI_SBSRSC => decode_fli_sbsrsc(&buf, dst)?,
-        FLI_COLOR64 => decode_fli_color64(&buf, dst)?,
-        FLI_LC => decode_fli_lc(&buf, dst)?,
-        FLI_BLACK => decode_fli_black(dst),
-        FLI_ICOLORS => decode_fli_icolors(dst),
-        FLI_BRUN => decode_fli_brun(&buf, dst)?,
-        FLI_COPY => decode_fli_copy(&buf, dst)?,
+        FLI_WRUN => {
+            decode_fli_wrun(&buf, dst)?;
+            changes.add_rect(screen);
+        },
+        FLI_COLOR256 => decode_fli_color256_with_changes(&buf, dst, changes)?,
+        FLI_SS2 => decode_fli_ss2_with_changes(&buf, dst, changes)?,
+        FLI_SBSRSC => {
+            decode_fli_sbsrsc(&buf, dst)?;
+            changes.add_rect(screen);
+        },
+        FLI_COLOR64 => decode_fli_color64_with_changes(&buf, dst, changes)?,
+        FLI_LC => decode_fli_lc_with_changes(&buf, dst, changes)?,
+        FLI_BLACK => {
+            decode_fli_black(dst);
+            changes.add_rect(screen);
+        },
+        FLI_ICOLORS => {
+            decode_fli_icolors(dst);
+            changes.add_palette(0..256);
+        },
+        FLI_BRUN => {
+            decode_fli_brun(&buf, dst)?;
+            changes.add_rect(screen);
+        },
+        FLI_COPY => {
+            decode_fli_copy(&buf, dst)?;
+            changes.add_rect(screen);
+        },
 
         // Postage stamps should not be decoded in the same loop as
         // the main animation; they have different sizes and work on
@@ -118,6 +399,204 @@ pub fn decode_chunk(magic: u16, buf: &[u8], dst: &mut RasterMut)
     Ok(())
 }
 
+/// Maximum length of a run of changed pixels that is considered to be
+/// an isolated change by `apply_pixel_tolerance`.
+const ISOLATED_CHANGE_LENGTH: usize = 2;
+
+/// Prepare a near-lossless frame for encoding.
+///
+/// Pixels in `next` whose colour is within `tolerance` (Euclidean
+/// distance in RGB, using the palette of `next`) of the pixel in
+/// `prev` are replaced by the pixel in `prev`, so that the encoders
+/// see them as unchanged.  Short, isolated runs of changed pixels are
+/// also dropped if they are within twice the tolerance.
+///
+/// The result is written to `dst`, a packed buffer of size
+/// `next.w * next.h`.
+///
+/// `prev` should be the frame as it will be decoded, not the
+/// original source frame.  Every pixel is compared against the source
+/// frame, so the error never accumulates beyond twice the tolerance.
+pub fn apply_pixel_tolerance(
+        prev: &Raster, next: &Raster, tolerance: u16, dst: &mut [u8])
+        -> FlicResult<()> {
+    if (prev.w != next.w) || (prev.h != next.h) {
+        return Err(FlicError::WrongResolution);
+    } else if dst.len() != next.w * next.h {
+        return Err(FlicError::BadInput);
+    }
+
+    let tol2 = (tolerance as u32) * (tolerance as u32);
+    let isolated_tol2 = 4 * tol2;
+    let pal = &next.pal;
+    let dist2 = |a: u8, b: u8| {
+        let a = 3 * a as usize;
+        let b = 3 * b as usize;
+        (0..3).map(|i| {
+            let d = (pal[a + i] as i32) - (pal[b + i] as i32);
+            (d * d) as u32
+        }).sum::<u32>()
+    };
+
+    let prev_start = prev.stride * prev.y;
+    let prev_end = prev.stride * (prev.y + prev.h);
+    let next_start = next.stride * next.y;
+    let next_end = next.stride * (next.y + next.h);
+
+    for ((p, n), d) in prev.buf[prev_start..prev_end].chunks(prev.stride)
+            .zip(next.buf[next_start..next_end].chunks(next.stride))
+            .zip(dst.chunks_mut(next.w)) {
+        let p = &p[prev.x..(prev.x + prev.w)];
+        let n = &n[next.x..(next.x + next.w)];
+
+        for ((&a, &b), c) in p.iter().zip(n.iter()).zip(d.iter_mut()) {
+            *c = if a == b || dist2(a, b) <= tol2 { a } else { b };
+        }
+
+        // Drop isolated changes.
+        let groups: Vec<Group> = GroupByEq::new(p.iter(), d.iter()).collect();
+        for g in groups {
+            if let Group::Diff(start, len) = g {
+                let end = start + len;
+                if len <= ISOLATED_CHANGE_LENGTH
+                        && start > 0 && end < p.len()
+                        && (start..end).all(|i| dist2(p[i], n[i]) <= isolated_tol2) {
+                    d[start..end].copy_from_slice(&p[start..end]);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Find the smallest sequence of skip/memset/memcpy packets that
+/// turns the line `old` into `new`, for the FLI_LC (`unit` = 1 byte)
+/// and FLI_SS2 (`unit` = 2 bytes) codecs.
+///
+/// Each packet is a skip byte followed by a memset or memcpy of up
+/// to 128 or 127 units.  Skips longer than 255 bytes cost an extra
+/// packet (skip 255, copy 0) for every 255 bytes.  If `set_end` is
+/// true, the last byte may instead be set by a separate opcode word.
+///
+/// Each packet is charged `penalty` bytes on top of its size, which
+/// trades a larger line for fewer packets.
+///
+/// Returns the packets, alternating between Skip and Memset/Memcpy,
+/// and whether the last byte should be set separately.  A final skip
+/// is never returned.
+fn optimal_packets(old: &[u8], new: &[u8], unit: usize, set_end: bool, penalty: usize)
+        -> (Vec<Packet>, bool) {
+    const MAX_MEMSET: usize = 128;
+    const MAX_MEMCPY: usize = 127;
+    const INF: usize = ::std::usize::MAX;
+
+    assert_eq!(old.len(), new.len());
+    assert!(unit == 1 || unit == 2);
+
+    let len = new.len();
+    let skip_cost = |s: usize| if s > 0 { (2 + penalty) * ((s - 1) / 255) } else { 0 };
+
+    // same_start[i]: start of the unchanged run ending at i.
+    // period[i]: length of the longest suffix of new[..i] that
+    // repeats with the given unit, i.e. can be memset.
+    let mut same_start = vec![0; len + 1];
+    let mut period = vec![0; len + 1];
+    for i in 1..(len + 1) {
+        same_start[i] = if old[i - 1] == new[i - 1] { same_start[i - 1] } else { i };
+        period[i] = if i > unit && new[i - 1] == new[i - 1 - unit] {
+            period[i - 1] + 1
+        } else {
+            ::std::cmp::min(i, unit)
+        };
+    }
+
+    // end_cost[i]: cost of the packets that end at i.
+    // start_cost[k]: cost of reaching k, where a packet may start.
+    let mut end_cost = vec![INF; len + 1];
+    let mut end_from = vec![(0, Packet::Skip(0)); len + 1];
+    let mut start_cost = vec![INF; len + 1];
+    let mut start_from = vec![0; len + 1];
+
+    // Returns the best packet end before k, skipping to k.  Packets
+    // that end deep inside an unchanged run are never useful.
+    let best_start = |k: usize, end_cost: &[usize]| {
+        let lo = same_start[k];
+        let hi = ::std::cmp::min(k, lo + MAX_MEMSET * unit);
+        let mut best = (INF, 0);
+        for j in lo..(hi + 1) {
+            if end_cost[j] != INF {
+                let c = end_cost[j] + skip_cost(k - j);
+                if c < best.0 {
+                    best = (c, j);
+                }
+            }
+        }
+        best
+    };
+
+    end_cost[0] = 0;
+    start_cost[0] = 0;
+
+    for i in 1..(len + 1) {
+        for n in 1..(MAX_MEMSET + 1) {
+            if n * unit > i {
+                break;
+            }
+
+            let k = i - n * unit;
+            if start_cost[k] == INF {
+                continue;
+            }
+
+            if n <= MAX_MEMCPY {
+                let c = start_cost[k] + 2 + penalty + n * unit;
+                if c < end_cost[i] {
+                    end_cost[i] = c;
+                    end_from[i] = (k, Packet::Memcpy(k, n * unit));
+                }
+            }
+
+            if n * unit <= period[i] {
+                let c = start_cost[k] + 2 + penalty + unit;
+                if c < end_cost[i] {
+                    end_cost[i] = c;
+                    end_from[i] = (k, Packet::Memset(k, n * unit));
+                }
+            }
+        }
+
+        let (c, j) = best_start(i, &end_cost);
+        start_cost[i] = c;
+        start_from[i] = j;
+    }
+
+    // Finish the line, possibly setting the last byte separately.
+    let mut best = best_start(len, &end_cost);
+    let mut use_set_end = false;
+    if set_end && len > 0 {
+        let (c, j) = best_start(len - 1, &end_cost);
+        if c != INF && c + 2 < best.0 {
+            best = (c + 2, j);
+            use_set_end = true;
+        }
+    }
+
+    // Trace back the packets.
+    let mut packets = Vec::new();
+    let mut i = best.1;
+    while i > 0 {
+        let (k, op) = end_from[i];
+        let j = start_from[k];
+        packets.push(op);
+        packets.push(Packet::Skip(k - j));
+        i = j;
+    }
+
+    packets.reverse();
+    (packets, use_set_end)
+}
+
 /*--------------------------------------------------------------*/
 
 impl<I: Iterator> GroupByEq<I>
@@ -412,7 +891,9 @@ impl Iterator for LinScale {
 
 #[cfg(test)]
 mod tests {
+    use ::{Raster,Rect};
     use super::{Group,GroupByEq,GroupByLC,GroupBySS2,GroupByValue,LinScale};
+    use super::{ChangeHint,FrameChanges,Packet,apply_pixel_tolerance,optimal_packets,verify_changed_spans};
 
     #[test]
     fn test_group_by_eq() {
@@ -481,6 +962,116 @@ mod tests {
         assert_eq!(&gs[..], &expected[..]);
     }
 
+    #[test]
+    fn test_apply_pixel_tolerance() {
+        let mut pal = [0; 3 * 256];
+        for c in 0..256 {
+            pal[3 * c + 0] = c as u8;
+            pal[3 * c + 1] = c as u8;
+            pal[3 * c + 2] = c as u8;
+        }
+
+        let xs = [ 10, 10, 10, 10, 10, 10, 10, 10, 10, 10 ];
+        let ys = [ 11, 10, 15, 10, 10, 40, 40, 40, 10, 15 ];
+        //          ^ within tolerance
+        //                  ^ isolated
+        //                          ^^^^^^^^^^ too far
+        //                                          ^ end of row
+        let expected = [ 10, 10, 10, 10, 10, 40, 40, 40, 10, 15 ];
+
+        let prev = Raster::new(10, 1, &xs, &pal);
+        let next = Raster::new(10, 1, &ys, &pal);
+        let mut dst = [0; 10];
+        apply_pixel_tolerance(&prev, &next, 5, &mut dst).expect("tolerance");
+        assert_eq!(&dst[..], &expected[..]);
+
+        apply_pixel_tolerance(&prev, &next, 0, &mut dst).expect("tolerance");
+        assert_eq!(&dst[..], &ys[..]);
+    }
+
+    #[test]
+    fn test_frame_changes() {
+        let mut changes = FrameChanges::default();
+        assert!(changes.is_empty());
+
+        changes.add_span(4, 2, 0);
+        changes.add_palette(7..7);
+        assert!(changes.is_empty());
+
+        changes.add_span(4, 2, 3);
+        changes.add_span(1, 5, 2);
+        assert_eq!(changes.rect, Some(Rect { x: 1, y: 2, w: 6, h: 4 }));
+
+        let mut other = FrameChanges::default();
+        other.add_palette(10..12);
+        other.add_palette(2..3);
+        other.add_rect(Rect { x: 0, y: 0, w: 1, h: 1 });
+        changes.merge(&other);
+        assert_eq!(changes.rect, Some(Rect { x: 0, y: 0, w: 7, h: 6 }));
+        assert_eq!(changes.palette, Some(2..12));
+    }
+
+    #[test]
+    fn test_change_hint() {
+        const SCREEN_W: usize = 4;
+        const SCREEN_H: usize = 4;
+        let buf1 = [0; SCREEN_W * SCREEN_H];
+        let mut buf2 = [0; SCREEN_W * SCREEN_H];
+        let pal = [0; 3 * 256];
+        buf2[SCREEN_W * 2 + 1] = 1;
+
+        let rects = [ Rect { x: 1, y: 1, w: 2, h: 2 }, Rect { x: 0, y: 2, w: 1, h: 1 } ];
+        let spans = ChangeHint::Rects(&rects).changed_spans(SCREEN_W, SCREEN_H).expect("spans");
+        assert_eq!(spans, vec![ vec![], vec![ 1..3 ], vec![ 0..3 ], vec![] ]);
+
+        let rows = ChangeHint::Rows(&[ false, true, false, false ])
+            .changed_spans(SCREEN_W, SCREEN_H).expect("spans");
+        assert_eq!(rows, vec![ vec![], vec![ 0..4 ], vec![], vec![] ]);
+
+        let rects = [ Rect { x: 1, y: 3, w: 2, h: 2 } ];
+        assert!(ChangeHint::Rects(&rects).changed_spans(SCREEN_W, SCREEN_H).is_err());
+        let rects = [ Rect { x: 1, y: 0, w: ::std::usize::MAX, h: 1 } ];
+        assert!(ChangeHint::Rects(&rects).changed_spans(SCREEN_W, SCREEN_H).is_err());
+        let rects = [ Rect { x: 0, y: 1, w: 1, h: ::std::usize::MAX } ];
+        assert!(ChangeHint::Rects(&rects).changed_spans(SCREEN_W, SCREEN_H).is_err());
+        assert!(ChangeHint::Rows(&[ true ]).changed_spans(SCREEN_W, SCREEN_H).is_err());
+
+        let prev = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let next = Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+        assert!(verify_changed_spans(&prev, &next, &spans).is_ok());
+
+        let narrow = vec![ vec![], vec![ 1..3 ], vec![ 2..3 ], vec![] ];
+        assert!(verify_changed_spans(&prev, &next, &narrow).is_err());
+    }
+
+    #[test]
+    fn test_optimal_packets_lc() {
+        let xs = [ 1, 2, 3, 4, 5, 6, 7, 8, 9, 10 ];
+        let ys = [ 0, 2, 0, 4, 5, 6, 7, 8, 0, 0 ];
+        //         ^^^^^^^  copy 3 is cheaper than two packets
+        let expected = [
+            Packet::Skip(0), Packet::Memcpy(0, 3),
+            Packet::Skip(5), Packet::Memset(8, 2) ];
+
+        let (packets, set_end) = optimal_packets(&xs, &ys, 1, false, 0);
+        assert_eq!(&packets[..], &expected[..]);
+        assert!(!set_end);
+    }
+
+    #[test]
+    fn test_optimal_packets_ss2() {
+        let xs = [ 1, 2, 3, 4, 5, 6, 7, 8, 9 ];
+        let ys = [ 1, 7, 8, 7, 8, 7, 8, 8, 0 ];
+        //            ^^^^^^^^^^^^^^^^  memset of odd start
+        //                                 ^ set end
+        let expected = [
+            Packet::Skip(1), Packet::Memset(1, 6) ];
+
+        let (packets, set_end) = optimal_packets(&xs, &ys, 2, true, 0);
+        assert_eq!(&packets[..], &expected[..]);
+        assert!(set_end);
+    }
+
     #[test]
     fn test_linscale() {
         fn linscale(sw: usize, dw: usize, dx: usize) -> usize {
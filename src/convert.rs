@@ -0,0 +1,380 @@
+//! Conversion between FLI and FLC files.
+//!
+//! FLI files are always 320x200 with a 6-bit palette and a speed in
+//! jiffies, while FLC files may have any size, an 8-bit palette, and
+//! a speed in milliseconds.  Frames are decoded, resized when needed,
+//! and re-encoded.  FLI output never uses FLI_SS2 or postage stamps.
+
+use std::path::Path;
+
+use ::{FlicError,FlicFile,FlicFileWriter,FlicResult,Raster,RasterMut,Rect};
+use ::codec::scale_raster;
+use ::edit::write_replacing;
+use ::flic::FLIH_MAGIC;
+
+/// Width of a FLI file.
+const FLI_W: usize = 320;
+
+/// Height of a FLI file.
+const FLI_H: usize = 200;
+
+/// Aspect ratio of a FLI file.
+const FLI_ASPECT: (u16, u16) = (6, 5);
+
+/// Maximum number of frames in a FLI file.
+const FLI_MAX_FRAMES: u16 = 4000;
+
+/// FLIC file format.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum FlicFormat {
+    /// Original Animator FLI, 320x200.
+    Fli,
+
+    /// Animator Pro FLC.
+    Flc,
+}
+
+/// How to fit frames into a screen of a different size.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum ResizeMode {
+    /// Stretch the frames to fill the screen.
+    Scale,
+
+    /// Scale the frames to fit the screen, keeping their aspect
+    /// ratio, and fill the borders with the given colour index.
+    Letterbox(u8),
+}
+
+/*--------------------------------------------------------------*/
+
+/// Convert a FLIC to the given format.
+///
+/// FLC output keeps the source's size.  FLI output is scaled or
+/// letterboxed to 320x200, taking the aspect ratios into account.
+/// The palette and speed are converted to suit the output format.
+///
+/// The output replaces `dst` only once it has been written in full,
+/// so `dst` may be the same file as `src`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use flic::convert::{FlicFormat,ResizeMode};
+///
+/// flic::convert::convert(Path::new("in.flc"), Path::new("out.fli"),
+///         FlicFormat::Fli, ResizeMode::Letterbox(0));
+/// ```
+pub fn convert(src: &Path, dst: &Path, format: FlicFormat, resize: ResizeMode)
+        -> FlicResult<()> {
+    write_replacing(dst, |tmp| convert_to(src, tmp, format, resize))
+}
+
+fn convert_to(src: &Path, dst: &Path, format: FlicFormat, resize: ResizeMode)
+        -> FlicResult<()> {
+    let mut fin = FlicFile::open(src)?;
+    let src_fli = fin.magic() == FLIH_MAGIC;
+    let sw = fin.width() as usize;
+    let sh = fin.height() as usize;
+    let src_aspect = (fin.aspect_x(), fin.aspect_y());
+
+    let speed_msec = if src_fli {
+        jiffies_to_msec_rounded(fin.speed_jiffies())
+    } else {
+        fin.speed_msec()
+    };
+
+    let (mut fout, dw, dh, dst_aspect) = match format {
+        FlicFormat::Fli => {
+            if fin.frame_count() > FLI_MAX_FRAMES {
+                return Err(FlicError::ExceededLimit);
+            }
+
+            let speed_jiffies = if src_fli {
+                fin.speed_jiffies()
+            } else {
+                msec_to_jiffies_rounded(speed_msec)
+            };
+
+            (FlicFileWriter::create_fli(dst, speed_jiffies)?, FLI_W, FLI_H, FLI_ASPECT)
+        },
+        FlicFormat::Flc => {
+            let mut fout = FlicFileWriter::create(dst, sw as u16, sh as u16, speed_msec)?;
+            fout.set_aspect_ratio(src_aspect.0, src_aspect.1);
+            (fout, sw, sh, src_aspect)
+        },
+    };
+
+    fout.set_creator(fin.creator(), fin.creation_time());
+
+    let rect = fit_rect(sw, sh, src_aspect, dw, dh, dst_aspect, resize);
+    let border = match resize {
+        ResizeMode::Scale => 0,
+        ResizeMode::Letterbox(c) => c,
+    };
+
+    let pal_mode = match (src_fli, format) {
+        (false, FlicFormat::Fli) => PaletteMode::To6Bit,
+        (true, FlicFormat::Flc) => PaletteMode::To8Bit,
+        _ => PaletteMode::Keep,
+    };
+
+    let res = convert_frames(&mut fin, &mut fout, dw, dh, rect, border, pal_mode);
+
+    match res {
+        Ok(_) => fout.close().map(|_| ()),
+        Err(e) => {
+            let _ = fout.close();
+            Err(e)
+        },
+    }
+}
+
+fn convert_frames(fin: &mut FlicFile, fout: &mut FlicFileWriter,
+        dw: usize, dh: usize, rect: Rect, border: u8, pal_mode: PaletteMode)
+        -> FlicResult<()> {
+    let sw = fin.width() as usize;
+    let sh = fin.height() as usize;
+    let mut src_buf = vec![0; sw * sh];
+    let mut src_pal = vec![0; 3 * 256];
+    let mut prev_buf = vec![0; dw * dh];
+    let mut prev_pal = vec![0; 3 * 256];
+    let mut next_buf = vec![0; dw * dh];
+    let mut next_pal = vec![0; 3 * 256];
+    let mut first = true;
+
+    loop {
+        let res = fin.read_next_frame(
+                &mut RasterMut::new(sw, sh, &mut src_buf, &mut src_pal))?;
+
+        for c in next_buf.iter_mut() {
+            *c = border;
+        }
+
+        scale_raster(&Raster::new(sw, sh, &src_buf, &src_pal),
+                &mut RasterMut::with_offset(rect.x, rect.y, rect.w, rect.h, dw,
+                        &mut next_buf, &mut next_pal));
+        convert_palette(pal_mode, &src_pal, &mut next_pal);
+
+        let next = Raster::new(dw, dh, &next_buf, &next_pal);
+        if first {
+            fout.write_next_frame(None, &next)?;
+        } else {
+            fout.write_next_frame(Some(&Raster::new(dw, dh, &prev_buf, &prev_pal)), &next)?;
+        }
+
+        if res.looped {
+            break;
+        }
+
+        prev_buf.copy_from_slice(&next_buf);
+        prev_pal.copy_from_slice(&next_pal);
+        first = false;
+    }
+
+    Ok(())
+}
+
+/// Convert an 8-bit palette to a 6-bit palette, rounding to the
+/// nearest level.
+pub fn pal_8bit_to_6bit(src: &[u8], dst: &mut [u8]) {
+    for (d, &s) in dst.iter_mut().zip(src.iter()) {
+        *d = ((s as u32 * 63 + 127) / 255) as u8;
+    }
+}
+
+/// Round an 8-bit palette to the levels of a decoded FLI palette.
+///
+/// Decoded FLI palettes are 6-bit levels multiplied by 4, which is
+/// also what the FLI_COLOR64 encoder expects.
+pub fn pal_8bit_to_fli_levels(src: &[u8], dst: &mut [u8]) {
+    pal_8bit_to_6bit(src, dst);
+    for c in dst.iter_mut() {
+        *c = 4 * *c;
+    }
+}
+
+/// Convert a 6-bit palette to an 8-bit palette, using the full range.
+pub fn pal_6bit_to_8bit(src: &[u8], dst: &mut [u8]) {
+    for (d, &s) in dst.iter_mut().zip(src.iter()) {
+        *d = ((::std::cmp::min(s, 63) as u32 * 255 + 31) / 63) as u8;
+    }
+}
+
+/// Convert a speed in milliseconds to jiffies, rounding to the
+/// nearest jiffy.
+pub fn msec_to_jiffies_rounded(speed_msec: u32)
+        -> u16 {
+    ::std::cmp::min((speed_msec as u64 * 70 + 500) / 1000, ::std::u16::MAX as u64) as u16
+}
+
+/// Convert a speed in jiffies to milliseconds, rounding to the
+/// nearest millisecond.
+pub fn jiffies_to_msec_rounded(speed_jiffies: u16)
+        -> u32 {
+    (speed_jiffies as u32 * 1000 + 35) / 70
+}
+
+/*--------------------------------------------------------------*/
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum PaletteMode {
+    Keep,
+    To6Bit,
+    To8Bit,
+}
+
+/// Convert the palette as decoded from the source FLIC to the
+/// palette to encode.
+fn convert_palette(mode: PaletteMode, src: &[u8], dst: &mut [u8]) {
+    match mode {
+        PaletteMode::Keep => dst.copy_from_slice(src),
+        PaletteMode::To6Bit => pal_8bit_to_fli_levels(src, dst),
+        PaletteMode::To8Bit => {
+            let pal6: Vec<u8> = src.iter().map(|&c| c / 4).collect();
+            pal_6bit_to_8bit(&pal6, dst);
+        },
+    }
+}
+
+/// Find where to place a sw x sh image on a dw x dh screen.
+///
+/// An aspect ratio of x:y means that x pixels across are as long as
+/// y pixels down.
+fn fit_rect(sw: usize, sh: usize, src_aspect: (u16, u16),
+        dw: usize, dh: usize, dst_aspect: (u16, u16), resize: ResizeMode)
+        -> Rect {
+    if let ResizeMode::Scale = resize {
+        return Rect { x: 0, y: 0, w: dw, h: dh };
+    }
+
+    let (sax, say) = (::std::cmp::max(src_aspect.0, 1) as u64, ::std::cmp::max(src_aspect.1, 1) as u64);
+    let (dax, day) = (::std::cmp::max(dst_aspect.0, 1) as u64, ::std::cmp::max(dst_aspect.1, 1) as u64);
+    let (sw64, sh64, dw64, dh64) = (sw as u64, sh as u64, dw as u64, dh as u64);
+
+    // Compare the displayed aspect ratios, (sw / sax) : (sh / say).
+    if sw64 * say * dh64 * dax >= dw64 * day * sh64 * sax {
+        let h = (dw64 * day * sh64 * sax + (dax * sw64 * say) / 2) / (dax * sw64 * say);
+        let h = ::std::cmp::max(1, ::std::cmp::min(h, dh64)) as usize;
+        Rect { x: 0, y: (dh - h) / 2, w: dw, h: h }
+    } else {
+        let w = (dh64 * dax * sw64 * say + (day * sh64 * sax) / 2) / (day * sh64 * sax);
+        let w = ::std::cmp::max(1, ::std::cmp::min(w, dw64)) as usize;
+        Rect { x: (dw - w) / 2, y: 0, w: w, h: dh }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{FlicFile,Raster,RasterMut,Rect};
+    use ::flic::{FLIH_MAGIC,FLIHR_MAGIC};
+    use ::flic::tests::{TempFile,write_test_flic};
+    use super::*;
+    use super::fit_rect;
+
+    #[test]
+    fn test_fit_rect() {
+        let r = fit_rect(640, 480, (1, 1), 320, 200, (6, 5), ResizeMode::Scale);
+        assert_eq!(r, Rect { x: 0, y: 0, w: 320, h: 200 });
+
+        // 640x480 square pixels and 320x200 FLI pixels are both 4:3.
+        let r = fit_rect(640, 480, (1, 1), 320, 200, (6, 5), ResizeMode::Letterbox(0));
+        assert_eq!(r, Rect { x: 0, y: 0, w: 320, h: 200 });
+
+        let r = fit_rect(100, 100, (1, 1), 320, 200, (6, 5), ResizeMode::Letterbox(0));
+        assert_eq!(r, Rect { x: 40, y: 0, w: 240, h: 200 });
+
+        let r = fit_rect(200, 100, (1, 1), 320, 200, (6, 5), ResizeMode::Letterbox(0));
+        assert_eq!(r, Rect { x: 0, y: 33, w: 320, h: 133 });
+    }
+
+    #[test]
+    fn test_convert_palette_and_speed() {
+        let mut pal6 = [0; 4];
+        pal_8bit_to_6bit(&[ 0, 2, 128, 255 ], &mut pal6);
+        assert_eq!(pal6, [ 0, 0, 32, 63 ]);
+
+        let mut pal8 = [0; 4];
+        pal_6bit_to_8bit(&[ 0, 1, 32, 63 ], &mut pal8);
+        assert_eq!(pal8, [ 0, 4, 130, 255 ]);
+
+        assert_eq!(msec_to_jiffies_rounded(70), 5);
+        assert_eq!(jiffies_to_msec_rounded(5), 71);
+    }
+
+    #[test]
+    fn test_convert() {
+        const SCREEN_W: usize = 100;
+        const SCREEN_H: usize = 100;
+        let buf0: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|i| ((i % SCREEN_W) / 10) as u8).collect();
+        let buf1: Vec<u8> = buf0.iter().map(|&c| c + 1).collect();
+        let pal: Vec<u8> = (0..(3 * 256)).map(|i| i as u8).collect();
+        let raster0 = Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal);
+        let raster1 = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+
+        let flc = TempFile::new("convert.flc");
+        let fli = TempFile::new("convert.fli");
+        let flc2 = TempFile::new("convert2.flc");
+
+        write_test_flic(&flc, &[raster0, raster1]);
+
+        convert(&flc, &fli, FlicFormat::Fli, ResizeMode::Letterbox(0xFF)).expect("convert");
+
+        let mut flic = FlicFile::open(&fli).expect("open");
+        assert_eq!(flic.magic(), FLIH_MAGIC);
+        assert_eq!((flic.width(), flic.height()), (320, 200));
+        assert_eq!(flic.frame_count(), 2);
+        assert_eq!(flic.speed_jiffies(), 5);
+
+        let mut buf = vec![0; 320 * 200];
+        let mut pal = vec![0; 3 * 256];
+        for &(i, ref src) in [ (0, &buf0), (1, &buf1) ].iter() {
+            flic.read_next_frame(&mut RasterMut::new(320, 200, &mut buf, &mut pal))
+                    .expect("read");
+            assert_eq!(buf[320 * 100 + 39], 0xFF);
+            assert_eq!(buf[320 * 100 + 280], 0xFF);
+            assert_eq!(buf[320 * 100 + 40], src[0]);
+            assert_eq!(buf[320 * 100 + 279], src[SCREEN_W - 1]);
+            assert_eq!(pal[3 * 128], 128, "frame {}", i);
+            assert_eq!(pal[3 * 255], 252);
+        }
+
+        convert(&fli, &flc2, FlicFormat::Flc, ResizeMode::Scale).expect("convert");
+
+        let mut flic = FlicFile::open(&flc2).expect("open");
+        assert_eq!(flic.magic(), FLIHR_MAGIC);
+        assert_eq!((flic.width(), flic.height()), (320, 200));
+        assert_eq!((flic.aspect_x(), flic.aspect_y()), (6, 5));
+        assert_eq!(flic.speed_msec(), 71);
+
+        flic.read_next_frame(&mut RasterMut::new(320, 200, &mut buf, &mut pal))
+                .expect("read");
+        assert_eq!(buf[320 * 100 + 40], buf0[0]);
+        assert_eq!(pal[3 * 255], 255);
+    }
+
+    #[test]
+    fn test_convert_in_place() {
+        const SCREEN_W: usize = 32;
+        const SCREEN_H: usize = 20;
+        let pal: Vec<u8> = (0..(3 * 256)).map(|x| (x / 3) as u8).collect();
+        let buf0: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| x as u8).collect();
+        let buf1: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x * 3) as u8).collect();
+        let filename = TempFile::new("convert-in-place.flc");
+        write_test_flic(&filename, &[
+                Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal),
+                Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal) ]);
+
+        convert(&filename, &filename, FlicFormat::Fli, ResizeMode::Scale).expect("convert");
+
+        let mut buf = [0; 320 * 200];
+        let mut out_pal = [0; 3 * 256];
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.magic(), FLIH_MAGIC);
+        assert_eq!(flic.frame_count(), 2);
+
+        flic.read_next_frame(&mut RasterMut::new(320, 200, &mut buf, &mut out_pal))
+                .expect("read");
+        assert_eq!(buf[320 * 199 + 319], buf0[SCREEN_W * SCREEN_H - 1]);
+    }
+}
@@ -0,0 +1,591 @@
+//! FLIC editing operations.
+//!
+//! These operations copy the existing frame chunks byte-for-byte
+//! wherever possible.  Only frames whose previous frame changes, such
+//! as the first frame after a cut, are decoded and re-encoded.
+
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use ::{FlicError,FlicFile,FlicFileWriter,FlicResult,Raster,RasterMut};
+use ::flic::FLIH_MAGIC;
+
+/// A range of frames from a FLIC file.
+pub struct FlicSegment<'a> {
+    pub filename: &'a Path,
+    pub frames: Range<u16>,
+}
+
+/// Frame order for `reverse`.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum ReverseMode {
+    /// Play the frames from last to first.
+    Backward,
+
+    /// Play the frames from first to last, then back again.  The
+    /// first and last frames are not repeated, so the animation loops
+    /// smoothly.
+    PingPong,
+}
+
+/// Screen buffer and palette.
+struct Screen {
+    buf: Vec<u8>,
+    pal: Vec<u8>,
+}
+
+/*--------------------------------------------------------------*/
+
+/// Write a new FLIC containing the given ranges of frames, in order.
+///
+/// All of the source FLICs must have the same format and dimensions.
+/// The speed, creator, and aspect ratio are taken from the first
+/// source FLIC.
+///
+/// The output replaces `dst` only once it has been written in full,
+/// so `dst` may be one of the source files.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use flic::edit::FlicSegment;
+///
+/// let segments = [
+///     FlicSegment { filename: Path::new("a.flc"), frames: 0..10 },
+///     FlicSegment { filename: Path::new("b.flc"), frames: 5..20 } ];
+///
+/// flic::edit::splice(&segments, Path::new("ex.flc"));
+/// ```
+pub fn splice(segments: &[FlicSegment], dst: &Path)
+        -> FlicResult<()> {
+    if segments.is_empty() {
+        return Err(FlicError::BadInput);
+    }
+
+    write_replacing(dst, |tmp| splice_to(segments, tmp))
+}
+
+/// Write a new FLIC containing the given range of frames.
+pub fn extract(src: &Path, frames: Range<u16>, dst: &Path)
+        -> FlicResult<()> {
+    splice(&[FlicSegment { filename: src, frames: frames }], dst)
+}
+
+/// Write a new FLIC with the given range of frames removed.
+pub fn cut(src: &Path, frames: Range<u16>, dst: &Path)
+        -> FlicResult<()> {
+    let frame_count = FlicFile::open(src)?.frame_count();
+    if frames.start >= frames.end || frames.end > frame_count {
+        return Err(FlicError::BadInput);
+    }
+
+    let mut segments = Vec::new();
+    if frames.start > 0 {
+        segments.push(FlicSegment { filename: src, frames: 0..frames.start });
+    }
+    if frames.end < frame_count {
+        segments.push(FlicSegment { filename: src, frames: frames.end..frame_count });
+    }
+
+    splice(&segments, dst)
+}
+
+/// Write a new FLIC containing all of the frames of the source
+/// FLICs, one after another.
+pub fn concat(srcs: &[&Path], dst: &Path)
+        -> FlicResult<()> {
+    let mut segments = Vec::with_capacity(srcs.len());
+    for &src in srcs.iter() {
+        let frame_count = FlicFile::open(src)?.frame_count();
+        segments.push(FlicSegment { filename: src, frames: 0..frame_count });
+    }
+
+    splice(&segments, dst)
+}
+
+/// Write a new FLIC that plays the source FLIC's frames in reverse.
+///
+/// The frames are decoded in blocks of at most `max_frames` frames,
+/// re-decoding from the nearest keyframe for each block, so that the
+/// whole animation need not be held in memory.
+///
+/// The output replaces `dst` only once it has been written in full,
+/// so `dst` may be the same file as `src`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::path::Path;
+/// use flic::edit::ReverseMode;
+///
+/// flic::edit::reverse(Path::new("in.flc"), Path::new("out.flc"),
+///         ReverseMode::PingPong, 32);
+/// ```
+pub fn reverse(src: &Path, dst: &Path, mode: ReverseMode, max_frames: usize)
+        -> FlicResult<()> {
+    if max_frames <= 0 {
+        return Err(FlicError::BadInput);
+    }
+
+    write_replacing(dst, |tmp| reverse_to(src, tmp, mode, max_frames))
+}
+
+/// Write a file through `write`, then move it into place at `dst`.
+///
+/// The file is first written to a temporary path beside `dst`, so
+/// `dst` may also be one of the files being read.  On error, the
+/// temporary file is removed and `dst` is left untouched.
+pub fn write_replacing<F>(dst: &Path, write: F)
+        -> FlicResult<()>
+        where F: FnOnce(&Path) -> FlicResult<()> {
+    let mut name = match dst.file_name() {
+        Some(name) => name.to_os_string(),
+        None => return Err(FlicError::BadInput),
+    };
+
+    name.push(".tmp");
+    let tmp = dst.with_file_name(name);
+
+    let res = write(&tmp)
+        .and_then(|_| fs::rename(&tmp, dst).map_err(FlicError::from));
+
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+
+    res
+}
+
+/*--------------------------------------------------------------*/
+
+impl Screen {
+    fn new(w: usize, h: usize) -> Self {
+        Screen {
+            buf: vec![0; w * h],
+            pal: vec![0; 3 * 256],
+        }
+    }
+
+    fn clear(&mut self) {
+        for c in self.buf.iter_mut() {
+            *c = 0;
+        }
+        for c in self.pal.iter_mut() {
+            *c = 0;
+        }
+    }
+
+    fn copy_from(&mut self, other: &Screen) {
+        self.buf.copy_from_slice(&other.buf);
+        self.pal.copy_from_slice(&other.pal);
+    }
+}
+
+/// Output frames through a FLIC writer, keeping track of the first
+/// and previous frames.
+struct FrameSink<'a> {
+    fout: &'a mut FlicFileWriter,
+    w: usize,
+    h: usize,
+    first: Option<Screen>,
+    prev: Option<Screen>,
+}
+
+impl<'a> FrameSink<'a> {
+    fn new(fout: &'a mut FlicFileWriter, w: usize, h: usize) -> Self {
+        FrameSink {
+            fout: fout,
+            w: w,
+            h: h,
+            first: None,
+            prev: None,
+        }
+    }
+
+    /// Encode the next frame.
+    fn write(&mut self, next: &Screen)
+            -> FlicResult<()> {
+        {
+            let (w, h) = (self.w, self.h);
+            let next_raster = Raster::new(w, h, &next.buf, &next.pal);
+            match self.prev {
+                Some(ref prev) =>
+                    self.fout.write_next_frame(
+                            Some(&Raster::new(w, h, &prev.buf, &prev.pal)), &next_raster)?,
+                None => self.fout.write_next_frame(None, &next_raster)?,
+            };
+        }
+
+        self.update(next);
+        Ok(())
+    }
+
+    /// Copy the next frame, which must have been encoded against the
+    /// previous frame, or a blank screen for the first frame.
+    fn write_raw(&mut self, raw: &[u8], next: &Screen)
+            -> FlicResult<()> {
+        self.fout.write_raw_frame(raw)?;
+        self.update(next);
+        Ok(())
+    }
+
+    fn update(&mut self, next: &Screen) {
+        let (w, h) = (self.w, self.h);
+        if self.first.is_none() {
+            let mut first = Screen::new(w, h);
+            first.copy_from(next);
+            self.first = Some(first);
+        }
+
+        self.prev.get_or_insert_with(|| Screen::new(w, h)).copy_from(next);
+    }
+
+    fn finish(self)
+            -> FlicResult<()> {
+        let (w, h) = (self.w, self.h);
+        match (self.first, self.prev) {
+            (Some(first), Some(prev)) =>
+                self.fout.write_next_frame(
+                        Some(&Raster::new(w, h, &prev.buf, &prev.pal)),
+                        &Raster::new(w, h, &first.buf, &first.pal))
+                    .map(|_| ()),
+            _ => Err(FlicError::Corrupted),
+        }
+    }
+}
+
+fn splice_to(segments: &[FlicSegment], dst: &Path)
+        -> FlicResult<()> {
+    let first = FlicFile::open(segments[0].filename)?;
+    for seg in segments.iter() {
+        let other = if seg.filename == segments[0].filename {
+            None
+        } else {
+            Some(FlicFile::open(seg.filename)?)
+        };
+
+        let flic = other.as_ref().unwrap_or(&first);
+        if flic.magic() != first.magic() {
+            return Err(FlicError::BadInput);
+        } else if flic.width() != first.width() || flic.height() != first.height() {
+            return Err(FlicError::WrongResolution);
+        } else if seg.frames.start >= seg.frames.end
+                || seg.frames.end > flic.frame_count() {
+            return Err(FlicError::BadInput);
+        }
+    }
+
+    let w = first.width() as usize;
+    let h = first.height() as usize;
+    let mut fout = if first.magic() == FLIH_MAGIC {
+        FlicFileWriter::create_fli(dst, first.speed_jiffies())?
+    } else {
+        FlicFileWriter::create(dst,
+                first.width(), first.height(), first.speed_msec())?
+    };
+
+    fout.set_creator(first.creator(), first.creation_time());
+    fout.set_aspect_ratio(first.aspect_x(), first.aspect_y());
+
+    match splice_frames(segments, &mut fout, w, h) {
+        Ok(_) => fout.close().map(|_| ()),
+        Err(e) => {
+            let _ = fout.close();
+            Err(e)
+        },
+    }
+}
+
+fn splice_frames(segments: &[FlicSegment], fout: &mut FlicFileWriter,
+        w: usize, h: usize)
+        -> FlicResult<()> {
+    let mut sink = FrameSink::new(fout, w, h);
+    let mut src = Screen::new(w, h);
+    let mut prev_seg: Option<&FlicSegment> = None;
+
+    for seg in segments.iter() {
+        let mut fin = FlicFile::open(seg.filename)?;
+        src.clear();
+
+        // The segment's first frame can be copied if it follows on
+        // from the previous output frame.
+        let contiguous = match prev_seg {
+            Some(p) => p.filename == seg.filename && p.frames.end == seg.frames.start,
+            None => seg.frames.start == 0,
+        };
+
+        for frame in 0..seg.frames.end {
+            fin.read_next_frame(&mut RasterMut::new(w, h, &mut src.buf, &mut src.pal))?;
+            if frame < seg.frames.start {
+                continue;
+            }
+
+            if frame > seg.frames.start || contiguous {
+                let raw = fin.read_raw_frame(frame)?;
+                sink.write_raw(&raw, &src)?;
+            } else {
+                sink.write(&src)?;
+            }
+        }
+
+        prev_seg = Some(seg);
+    }
+
+    sink.finish()
+}
+
+fn reverse_to(src: &Path, dst: &Path, mode: ReverseMode, max_frames: usize)
+        -> FlicResult<()> {
+    let mut fin = FlicFile::open(src)?;
+    let mut fout = if fin.magic() == FLIH_MAGIC {
+        FlicFileWriter::create_fli(dst, fin.speed_jiffies())?
+    } else {
+        FlicFileWriter::create(dst, fin.width(), fin.height(), fin.speed_msec())?
+    };
+
+    fout.set_creator(fin.creator(), fin.creation_time());
+    fout.set_aspect_ratio(fin.aspect_x(), fin.aspect_y());
+
+    match reverse_frames(&mut fin, &mut fout, mode, max_frames) {
+        Ok(_) => fout.close().map(|_| ()),
+        Err(e) => {
+            let _ = fout.close();
+            Err(e)
+        },
+    }
+}
+
+fn reverse_frames(fin: &mut FlicFile, fout: &mut FlicFileWriter,
+        mode: ReverseMode, max_frames: usize)
+        -> FlicResult<()> {
+    let w = fin.width() as usize;
+    let h = fin.height() as usize;
+    let frame_count = fin.frame_count() as usize;
+    let mut sink = FrameSink::new(fout, w, h);
+    let mut src = Screen::new(w, h);
+
+    // Decode the animation once to record the palette at each
+    // keyframe.  Frame 0 is always decoded from a blank screen.
+    let mut keyframes: Vec<(usize, Vec<u8>)> = vec![(0, vec![0; 3 * 256])];
+    for frame in 0..frame_count {
+        if frame > 0 && fin.is_keyframe(frame as u16) {
+            keyframes.push((frame, src.pal.clone()));
+        }
+
+        fin.read_next_frame(&mut RasterMut::new(w, h, &mut src.buf, &mut src.pal))?;
+
+        if mode == ReverseMode::PingPong {
+            sink.write(&src)?;
+        }
+    }
+
+    let (lo, mut hi) = match mode {
+        ReverseMode::Backward => (0, frame_count),
+        ReverseMode::PingPong => (1, frame_count.saturating_sub(1)),
+    };
+
+    let mut block: Vec<Screen> = Vec::new();
+    while hi > lo {
+        let start = if hi - lo > max_frames { hi - max_frames } else { lo };
+
+        // Re-decode from the nearest keyframe.
+        let &(keyframe, ref pal) = keyframes.iter()
+                .rev()
+                .find(|&&(k, _)| k <= start)
+                .expect("keyframe");
+
+        src.clear();
+        src.pal.copy_from_slice(pal);
+        fin.seek_frame(keyframe as u16)?;
+
+        for frame in keyframe..hi {
+            fin.read_next_frame(&mut RasterMut::new(w, h, &mut src.buf, &mut src.pal))?;
+            if frame >= start {
+                let idx = frame - start;
+                if idx >= block.len() {
+                    block.push(Screen::new(w, h));
+                }
+                block[idx].copy_from(&src);
+            }
+        }
+
+        for screen in block[0..(hi - start)].iter().rev() {
+            sink.write(screen)?;
+        }
+
+        hi = start;
+    }
+
+    sink.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use ::{FlicFile,Raster,RasterMut};
+    use ::flic::tests::TempFile;
+    use super::*;
+
+    const SCREEN_W: usize = 8;
+    const SCREEN_H: usize = 4;
+
+    fn make_frame(i: usize) -> Vec<u8> {
+        (0..(SCREEN_W * SCREEN_H)).map(|x| ((x / 3) * i) as u8).collect()
+    }
+
+    fn write_test_flic(filename: &Path, frames: &[Vec<u8>]) {
+        let pal = [0x80; 3 * 256];
+        let rasters: Vec<Raster> = frames.iter()
+            .map(|frame| Raster::new(SCREEN_W, SCREEN_H, frame, &pal))
+            .collect();
+
+        ::flic::tests::write_test_flic(filename, &rasters);
+    }
+
+    fn read_test_flic(filename: &Path) -> Vec<Vec<u8>> {
+        let mut flic = FlicFile::open(filename).expect("open");
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        let mut frames = Vec::new();
+
+        for _ in 0..flic.frame_count() {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+            frames.push(buf.to_vec());
+        }
+
+        // Ring frame.
+        flic.read_next_frame(
+                &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                .expect("read");
+        assert_eq!(&buf[..], &frames[0][..]);
+
+        frames
+    }
+
+    #[test]
+    fn test_cut() {
+        let frames: Vec<Vec<u8>> = (1..7).map(make_frame).collect();
+        let src = TempFile::new("cut-src.flc");
+        let dst = TempFile::new("cut-dst.flc");
+        write_test_flic(&src, &frames);
+
+        cut(&src, 2..4, &dst).expect("cut");
+
+        let expected = [ &frames[0], &frames[1], &frames[4], &frames[5] ];
+        let output = read_test_flic(&dst);
+        assert_eq!(output.len(), expected.len());
+        for (a, b) in output.iter().zip(expected.iter()) {
+            assert_eq!(&a[..], &b[..]);
+        }
+
+        // Frames away from the cut are copied verbatim.
+        let mut fin = FlicFile::open(&src).expect("open");
+        let mut fout = FlicFile::open(&dst).expect("open");
+        assert_eq!(fin.read_raw_frame(0).expect("raw"), fout.read_raw_frame(0).expect("raw"));
+        assert_eq!(fin.read_raw_frame(5).expect("raw"), fout.read_raw_frame(3).expect("raw"));
+    }
+
+    #[test]
+    fn test_cut_error_removes_output() {
+        let frames: Vec<Vec<u8>> = (1..5).map(make_frame).collect();
+        let src = TempFile::new("cut-error-src.flc");
+        let dst = TempFile::new("cut-error-dst.flc");
+        write_test_flic(&src, &frames);
+
+        // Give frame 2's first chunk an unknown type.
+        let raw = FlicFile::open(&src).expect("open").read_raw_frame(2).expect("raw");
+        let mut data = fs::read(&src).expect("read");
+        let pos = data.windows(raw.len()).position(|w| w == &raw[..]).expect("frame");
+        data[(pos + 20)..(pos + 22)].copy_from_slice(&[0xFF; 2]);
+        fs::write(&src, &data).expect("write");
+
+        assert!(cut(&src, 1..2, &dst).is_err());
+        assert!(!dst.exists());
+
+        // A failed in-place cut leaves the source intact.
+        assert!(cut(&src, 1..2, &src).is_err());
+        assert_eq!(fs::read(&src).expect("read"), data);
+    }
+
+    #[test]
+    fn test_cut_in_place() {
+        let frames: Vec<Vec<u8>> = (1..7).map(make_frame).collect();
+        let src = TempFile::new("cut-in-place.flc");
+        write_test_flic(&src, &frames);
+
+        cut(&src, 2..4, &src).expect("cut");
+
+        let expected = [ &frames[0], &frames[1], &frames[4], &frames[5] ];
+        let output = read_test_flic(&src);
+        assert_eq!(output.len(), expected.len());
+        for (a, b) in output.iter().zip(expected.iter()) {
+            assert_eq!(&a[..], &b[..]);
+        }
+    }
+
+    #[test]
+    fn test_concat() {
+        let frames1: Vec<Vec<u8>> = (1..4).map(make_frame).collect();
+        let frames2: Vec<Vec<u8>> = (7..10).map(make_frame).collect();
+        let src1 = TempFile::new("concat-src1.flc");
+        let src2 = TempFile::new("concat-src2.flc");
+        let dst = TempFile::new("concat-dst.flc");
+        write_test_flic(&src1, &frames1);
+        write_test_flic(&src2, &frames2);
+
+        concat(&[&src1, &src2], &dst).expect("concat");
+
+        let output = read_test_flic(&dst);
+        assert_eq!(output.len(), frames1.len() + frames2.len());
+        for (a, b) in output.iter().zip(frames1.iter().chain(frames2.iter())) {
+            assert_eq!(&a[..], &b[..]);
+        }
+    }
+
+    #[test]
+    fn test_reverse() {
+        let frames: Vec<Vec<u8>> = (1..8).map(make_frame).collect();
+        let src = TempFile::new("reverse-src.flc");
+        let dst = TempFile::new("reverse-dst.flc");
+        write_test_flic(&src, &frames);
+
+        reverse(&src, &dst, ReverseMode::Backward, 3).expect("reverse");
+
+        let output = read_test_flic(&dst);
+        assert_eq!(output.len(), frames.len());
+        for (a, b) in output.iter().zip(frames.iter().rev()) {
+            assert_eq!(&a[..], &b[..]);
+        }
+
+        reverse(&src, &dst, ReverseMode::PingPong, 2).expect("reverse");
+
+        let expected: Vec<&Vec<u8>> = frames.iter()
+            .chain(frames[1..(frames.len() - 1)].iter().rev())
+            .collect();
+        let output = read_test_flic(&dst);
+        assert_eq!(output.len(), expected.len());
+        for (a, b) in output.iter().zip(expected.iter()) {
+            assert_eq!(&a[..], &b[..]);
+        }
+    }
+
+    #[test]
+    fn test_reverse_in_place() {
+        let frames: Vec<Vec<u8>> = (1..8).map(make_frame).collect();
+        let src = TempFile::new("reverse-in-place.flc");
+        write_test_flic(&src, &frames);
+
+        reverse(&src, &src, ReverseMode::Backward, 3).expect("reverse");
+
+        let output = read_test_flic(&src);
+        assert_eq!(output.len(), frames.len());
+        for (a, b) in output.iter().zip(frames.iter().rev()) {
+            assert_eq!(&a[..], &b[..]);
+        }
+    }
+}
@@ -34,6 +34,10 @@ pub enum FlicError {
     ExceededLimit {
         description("Exceeded limit")
     }
+    BadRaster(reason: &'static str) {
+        description("Bad raster")
+        display("Bad raster: {}", reason)
+    }
 
     Io(err: io::Error) {
         from()
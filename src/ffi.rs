@@ -659,6 +659,9 @@ pub extern "C" fn flicrs_read_next_frame(
 /*--------------------------------------------------------------*/
 
 /// Allocate a new raster.
+///
+/// Returns null if the raster does not fit in the buffer, or the
+/// palette does not have 256 entries.
 #[no_mangle]
 pub extern "C" fn flicrs_raster_alloc(
         x: size_t, y: size_t, w: size_t, h: size_t, stride: size_t,
@@ -672,13 +675,22 @@ pub extern "C" fn flicrs_raster_alloc(
 
     let buf_slice = unsafe{ slice::from_raw_parts(buf, buf_len) };
     let pal_slice = unsafe{ slice::from_raw_parts(pal, pal_len) };
-    let raster = Raster::with_offset(x, y, w, h, stride, buf_slice, pal_slice);
+    let raster = match Raster::try_with_offset(x, y, w, h, stride, buf_slice, pal_slice) {
+        Ok(raster) => raster,
+        Err(e) => {
+            printerrorln!(e);
+            return ptr::null_mut();
+        },
+    };
     let rptr = Box::into_raw(Box::new(raster));
     let cptr: *mut CRaster = unsafe{ mem::transmute(rptr) };
     cptr
 }
 
 /// Allocate a new raster.
+///
+/// Returns null if the raster does not fit in the buffer, or the
+/// palette does not have 256 entries.
 #[no_mangle]
 pub extern "C" fn flicrs_raster_mut_alloc(
         x: size_t, y: size_t, w: size_t, h: size_t, stride: size_t,
@@ -692,7 +704,13 @@ pub extern "C" fn flicrs_raster_mut_alloc(
 
     let buf_slice = unsafe{ slice::from_raw_parts_mut(buf, buf_len) };
     let pal_slice = unsafe{ slice::from_raw_parts_mut(pal, pal_len) };
-    let raster = RasterMut::with_offset(x, y, w, h, stride, buf_slice, pal_slice);
+    let raster = match RasterMut::try_with_offset(x, y, w, h, stride, buf_slice, pal_slice) {
+        Ok(raster) => raster,
+        Err(e) => {
+            printerrorln!(e);
+            return ptr::null_mut();
+        },
+    };
     let rptr = Box::into_raw(Box::new(raster));
     let cptr: *mut CRasterMut = unsafe{ mem::transmute(rptr) };
     cptr
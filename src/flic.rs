@@ -1,14 +1,24 @@
 //! FLIC implementation.
 
 use std::cmp::min;
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File,OpenOptions};
 use std::io::{Cursor,Read,Seek,SeekFrom,Write};
+use std::ops::Range;
 use std::path::{Path,PathBuf};
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use std::sync::{Condvar,Mutex,mpsc};
+use std::thread;
 use byteorder::LittleEndian as LE;
 use byteorder::{ReadBytesExt,WriteBytesExt};
 
-use ::{FlicError,FlicResult,Raster,RasterMut};
-use ::pstamp::{PostageStamp,write_pstamp_data};
+use ::{FlicError,FlicResult,Frame,Raster,RasterMut};
+use ::pstamp::{PostageStamp,PstampOptions,Thumbnail,get_pstamp_size_for,read_pstamp_size,write_pstamp_data_with_options};
+use ::quantize::{Dither,TruecolorFormat,quantize,remap};
+use ::ratectl::{MAX_RATE_TOLERANCE,RateBudget,RateControl,RateControlReport};
+use ::render::{RenderFormat,render};
+use ::timing::{FrameTiming,TimingReport};
 use codec::*;
 
 /// Magic for a FLI file - Original Animator FLI Files.
@@ -93,6 +103,7 @@ pub struct FlicFile {
     hdr: FlicHeader,
     frame_hdr: Vec<FlicFrame>,
     frame: usize,
+    screen: Option<Frame>,
 
     filename: PathBuf,
     file: File,
@@ -105,11 +116,144 @@ pub struct FlicFileWriter {
     hdr: FlicHeader,
     offset_frame1: u64,
     offset_frame2: u64,
+    encoder: FrameEncoder,
+    pixel_tolerance: u16,
+    rate: Option<RateControl>,
+    track_frames: bool,
+    append: bool,
+    state: Option<FlicWriterState>,
+    summary: EncodeSummary,
+    timing: Option<FrameTiming>,
 
     filename: PathBuf,
     file: Option<File>,
 }
 
+/// FLIC header editor, with a File handle.
+///
+/// Patches the 128-byte header of an existing FLIC in place, without
+/// rewriting the frames.  Opens and holds onto the file handle until
+/// it is closed.
+pub struct FlicHeaderEditor {
+    hdr: FlicHeader,
+    offset_frame1: u64,
+    offset_frame2: u64,
+
+    file: File,
+}
+
+/// Trade-off between encoding speed and file size.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum CompressionLevel {
+    /// Build FLI_LC and FLI_SS2 packets greedily.  This is the
+    /// default.
+    Fast,
+
+    /// Choose the FLI_LC and FLI_SS2 packets that give the smallest
+    /// possible chunks.  This is several times slower.
+    Max,
+}
+
+/// Encodes FLIC frames in memory.
+///
+/// Candidate encodings are tried in reusable scratch buffers, and
+/// only the smallest is kept, so frames can be written to sinks that
+/// do not support seeking.
+pub struct FrameEncoder {
+    flic_magic: u16,
+    level: CompressionLevel,
+    frame: Vec<u8>,
+    best: Cursor<Vec<u8>>,
+    trial: Cursor<Vec<u8>>,
+    info: FrameEncodeInfo,
+    changed_spans: Option<Vec<Vec<Range<usize>>>>,
+    verify_hints: bool,
+    pstamp: PstampOptions,
+}
+
+/// A chunk in an encoded frame.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct ChunkInfo {
+    /// Chunk type, e.g. `FLI_LC`.
+    pub magic: u16,
+
+    /// Size of the chunk in bytes, including the chunk header.
+    pub size: usize,
+}
+
+/// How a frame was encoded.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct FrameEncodeInfo {
+    /// Size of the frame in bytes, including the frame header.
+    pub size: usize,
+
+    /// Chunks written, in order.
+    pub chunks: Vec<ChunkInfo>,
+
+    /// Pixel data encodings that were tried and rejected because
+    /// they were no smaller than the chosen encoding.
+    pub rejected: Vec<ChunkInfo>,
+
+    /// True if a palette chunk was written.
+    pub palette: bool,
+
+    /// True if a postage stamp was written.
+    pub pstamp: bool,
+
+    /// True if the frame was dropped by rate control, i.e. written
+    /// as an empty frame.
+    pub dropped: bool,
+}
+
+/// Number of chunks and their total size, for one chunk type.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct ChunkStats {
+    pub count: usize,
+    pub size: u64,
+}
+
+/// Compression behaviour over all the frames written by a FLIC
+/// writer.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct EncodeSummary {
+    /// Number of frames written, including the ring frame.
+    pub frames: usize,
+
+    /// Total size of the frames in bytes.
+    pub size: u64,
+
+    /// Number of frames dropped by rate control.
+    pub dropped_frames: usize,
+
+    /// Number of frames copied with `write_raw_frame`.  Their chunks
+    /// are not included in the chunk statistics.
+    pub raw_frames: usize,
+
+    /// Number of palette chunks written.
+    pub palette_chunks: usize,
+
+    /// Number of postage stamps written.
+    pub pstamps: usize,
+
+    /// Chunks written, by chunk type.
+    pub chunks: BTreeMap<u16, ChunkStats>,
+
+    /// Rejected pixel data encodings, by chunk type.
+    pub rejected: BTreeMap<u16, ChunkStats>,
+}
+
+/// Frames tracked by the FLIC writer, used when appending or when
+/// encoding with a pixel tolerance.
+///
+/// The last frame is the frame as it will be decoded, which may
+/// differ from the frame supplied to the writer.
+struct FlicWriterState {
+    first_buf: Vec<u8>,
+    first_pal: Vec<u8>,
+    last_buf: Vec<u8>,
+    last_pal: Vec<u8>,
+}
+
 /// Size of a FLIC file header on disk.
 ///
 /// A FLIC file begins with a 128-byte header, described below.  All
@@ -185,6 +329,12 @@ pub const SIZE_OF_FLIC_FRAME: usize = 16;
 
 /// FLIC frame header.
 struct FlicFrame {
+    // Note: offset to the frame header.
+    offset: u64,
+
+    // Note: number of bytes in the frame, including the frame header.
+    size: u32,
+
     chunks: Vec<ChunkId>,
 }
 
@@ -228,6 +378,10 @@ pub struct FlicPlaybackResult {
     pub ended: bool,
     pub looped: bool,
     pub palette_updated: bool,
+
+    /// Pixels and palette entries modified by the frame, for partial
+    /// screen updates.
+    pub changes: FrameChanges,
 }
 
 /*--------------------------------------------------------------*/
@@ -258,6 +412,7 @@ impl FlicFile {
             hdr: hdr,
             frame_hdr: frame_hdr,
             frame: 0,
+            screen: None,
 
             filename: filename.to_path_buf(),
             file: file,
@@ -274,6 +429,11 @@ impl FlicFile {
         self.hdr.frame_count
     }
 
+    /// Get the file format identifier, FLIH_MAGIC or FLIHR_MAGIC.
+    pub fn magic(&self) -> u16 {
+        self.hdr.magic
+    }
+
     /// Get the FLIC width.
     pub fn width(&self) -> u16 {
         self.hdr.w
@@ -326,12 +486,85 @@ impl FlicFile {
     }
 
     /// Decode the postage stamp.
-    pub fn read_postage_stamp<'a>(&mut self, dst: &'a mut RasterMut<'a>)
+    ///
+    /// The postage stamp is scaled to fit the raster.  If the first
+    /// frame has no FLI_PSTAMP chunk and is not a single full-screen
+    /// image, the first frame is fully decoded and scaled down.
+    pub fn read_postage_stamp(&mut self, dst: &mut RasterMut)
+            -> FlicResult<()> {
+        if self.frame_hdr.is_empty() {
+            return Err(FlicError::Corrupted);
+        }
+
+        if self.feed_postage_stamp(dst)? {
+            Ok(())
+        } else {
+            self.read_postage_stamp_of_frame(0, dst)
+        }
+    }
+
+    /// Create a postage stamp by fully decoding the given frame and
+    /// scaling it down to fit the raster.
+    ///
+    /// The current playback position is not changed.
+    pub fn read_postage_stamp_of_frame(&mut self, frame: u16, dst: &mut RasterMut)
             -> FlicResult<()> {
+        if frame >= self.hdr.frame_count || frame as usize >= self.frame_hdr.len() {
+            return Err(FlicError::BadInput);
+        }
+
+        let w = self.hdr.w as usize;
+        let h = self.hdr.h as usize;
+        let mut buf = vec![0; w * h];
+        let mut pal = vec![0; 3 * 256];
+
+        {
+            let mut scratch = RasterMut::new(w, h, &mut buf, &mut pal);
+            for f in 0..(frame as usize + 1) {
+                for chunk in self.frame_hdr[f].chunks.iter() {
+                    self.file.seek(SeekFrom::Start(chunk.offset))?;
+
+                    let mut chunk_buf = vec![0; chunk.size as usize];
+                    self.file.read_exact(&mut chunk_buf)?;
+
+                    decode_chunk(chunk.magic, &chunk_buf, &mut scratch)?;
+                }
+            }
+        }
+
+        let scratch = Raster::new(w, h, &buf, &pal);
+        let rgb = average_pstamp_rgb(&scratch, dst.w, dst.h);
+        let pstamp = map_pstamp_rgb(&rgb, dst.w, dst.h, false);
+
+        for (src_row, dst_row) in pstamp.chunks(dst.w)
+                .zip(dst.buf.chunks_mut(dst.stride).skip(dst.y)) {
+            dst_row[dst.x..(dst.x + dst.w)].copy_from_slice(src_row);
+        }
+
+        make_pstamp_pal(dst);
+        Ok(())
+    }
+
+    /// Feed the first frame's chunks to the postage stamp creator.
+    ///
+    /// Returns true if the postage stamp was created, and is not
+    /// missing changes from delta chunks in the first frame.
+    fn feed_postage_stamp(&mut self, dst: &mut RasterMut)
+            -> FlicResult<bool> {
         let mut pstamp = PostageStamp::new(
                 self.hdr.w as usize, self.hdr.h as usize, dst);
 
+        let is_delta = |magic| magic == FLI_WRUN
+                || magic == FLI_SS2
+                || magic == FLI_SBSRSC
+                || magic == FLI_LC;
+        let has_delta = self.frame_hdr[0].chunks.iter().any(|chunk| is_delta(chunk.magic));
+
         for chunk in self.frame_hdr[0].chunks.iter() {
+            if is_delta(chunk.magic) {
+                continue;
+            }
+
             self.file.seek(SeekFrom::Start(chunk.offset))?;
 
             let mut buf = vec![0; chunk.size as usize];
@@ -339,13 +572,138 @@ impl FlicFile {
 
             let done = pstamp.feed(chunk.magic, &buf)?;
             if done {
-                break;
+                // A stored postage stamp image is complete.
+                return Ok(chunk.magic == FLI_PSTAMP || !has_delta);
             }
         }
 
+        Ok(false)
+    }
+
+    /// Get the postage stamp size stored in the first frame's
+    /// FLI_PSTAMP chunk, if there is one.
+    pub fn pstamp_native_size(&mut self)
+            -> FlicResult<Option<(usize, usize)>> {
+        let chunk = match self.frame_hdr.get(0)
+                .and_then(|f| f.chunks.iter().find(|c| c.magic == FLI_PSTAMP)) {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        self.file.seek(SeekFrom::Start(chunk.offset))?;
+        let mut buf = [0; 4];
+        self.file.read_exact(&mut buf)?;
+
+        match read_pstamp_size(&buf)? {
+            (0, _) | (_, 0) => Ok(None),
+            size => Ok(Some(size)),
+        }
+    }
+
+    /// Decode the postage stamp into a new thumbnail.
+    ///
+    /// The thumbnail has the postage stamp's native size if the FLIC
+    /// has one, or else the standard postage stamp size.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// if let Ok(mut flic) = flic::FlicFile::open(Path::new("ex.flc")) {
+    ///     if let Ok(thumbnail) = flic.read_thumbnail() {
+    ///         println!("{}x{}", thumbnail.w, thumbnail.h);
+    ///     }
+    /// }
+    /// ```
+    pub fn read_thumbnail(&mut self)
+            -> FlicResult<Thumbnail> {
+        let native_size = self.pstamp_native_size()?;
+        let (w, h) = match native_size {
+            Some(size) => size,
+            None => {
+                let (w, h) = get_pstamp_size_for(
+                        STANDARD_PSTAMP_W, STANDARD_PSTAMP_H,
+                        self.hdr.w as usize, self.hdr.h as usize);
+                (w as usize, h as usize)
+            },
+        };
+
+        if w <= 0 || h <= 0 {
+            return Err(FlicError::WrongResolution);
+        }
+
+        let mut thumbnail = Thumbnail::new(w, h, native_size);
+        self.read_postage_stamp(&mut thumbnail.as_raster_mut())?;
+        Ok(thumbnail)
+    }
+
+    /// Create a thumbnail of the standard postage stamp size by
+    /// fully decoding the given frame.
+    ///
+    /// The current playback position is not changed.
+    pub fn read_thumbnail_of_frame(&mut self, frame: u16)
+            -> FlicResult<Thumbnail> {
+        let native_size = self.pstamp_native_size()?;
+        let (w, h) = get_pstamp_size_for(
+                STANDARD_PSTAMP_W, STANDARD_PSTAMP_H,
+                self.hdr.w as usize, self.hdr.h as usize);
+
+        if w <= 0 || h <= 0 {
+            return Err(FlicError::WrongResolution);
+        }
+
+        let mut thumbnail = Thumbnail::new(w as usize, h as usize, native_size);
+        self.read_postage_stamp_of_frame(frame, &mut thumbnail.as_raster_mut())?;
+        Ok(thumbnail)
+    }
+
+    /// Returns true if the frame redraws the entire screen, i.e. it
+    /// does not depend on the previous frame's pixels.
+    ///
+    /// The frame may still modify the previous frame's palette.
+    pub fn is_keyframe(&self, frame: u16) -> bool {
+        match self.frame_hdr.get(frame as usize) {
+            Some(f) => f.chunks.iter().any(|chunk|
+                    chunk.magic == FLI_BLACK
+                    || chunk.magic == FLI_BRUN
+                    || chunk.magic == FLI_COPY),
+            None => false,
+        }
+    }
+
+    /// Set the next frame to be decoded.
+    ///
+    /// The raster buffer passed to `read_next_frame` must contain the
+    /// frame preceding the new position, unless the new position is a
+    /// keyframe, in which case only the palette is required.
+    pub fn seek_frame(&mut self, frame: u16)
+            -> FlicResult<()> {
+        if frame as usize >= self.frame_hdr.len() {
+            return Err(FlicError::BadInput);
+        }
+
+        self.frame = frame as usize;
         Ok(())
     }
 
+    /// Read the undecoded frame chunk, including the frame header.
+    ///
+    /// The frame number may refer to the ring frame.
+    pub fn read_raw_frame(&mut self, frame: u16)
+            -> FlicResult<Vec<u8>> {
+        if frame as usize >= self.frame_hdr.len() {
+            return Err(FlicError::BadInput);
+        }
+
+        let frame = &self.frame_hdr[frame as usize];
+        self.file.seek(SeekFrom::Start(frame.offset))?;
+
+        let mut buf = vec![0; frame.size as usize];
+        self.file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
     /// Decode the next frame in the FLIC.
     ///
     /// The raster buffer must contain the previous frame.
@@ -375,6 +733,7 @@ impl FlicFile {
             ended: false,
             looped: false,
             palette_updated: false,
+            changes: FrameChanges::default(),
         };
 
         if (self.hdr.w as usize != dst.w) || (self.hdr.h as usize != dst.h) {
@@ -388,7 +747,7 @@ impl FlicFile {
             let mut buf = vec![0; chunk.size as usize];
             self.file.read_exact(&mut buf)?;
 
-            decode_chunk(chunk.magic, &buf, dst)?;
+            decode_chunk_with_changes(chunk.magic, &buf, dst, &mut res.changes)?;
 
             res.palette_updated = res.palette_updated
                     || chunk_modifies_palette(chunk.magic);
@@ -408,6 +767,75 @@ impl FlicFile {
 
         Ok(res)
     }
+
+    /// Allocate a frame of the FLIC's size.
+    pub fn new_frame(&self) -> Frame {
+        Frame::new(self.hdr.w as usize, self.hdr.h as usize)
+    }
+
+    /// Decode the next frame into the FLIC's own indexed screen, and
+    /// render it into a truecolor buffer.
+    ///
+    /// The indexed screen is kept between calls, so this should not
+    /// be mixed with `read_next_frame`.  See `render::render` for the
+    /// meaning of `transparent` and `pitch`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::render::RenderFormat;
+    ///
+    /// if let Ok(ref mut flic) = flic::FlicFile::open(Path::new("ex.flc")) {
+    ///     let w = flic.width() as usize;
+    ///     let h = flic.height() as usize;
+    ///     let mut rgba = vec![0; 4 * w * h];
+    ///
+    ///     let res = flic.read_next_frame_rendered(
+    ///             RenderFormat::Rgba32, None, &mut rgba, 4 * w);
+    /// }
+    /// ```
+    pub fn read_next_frame_rendered(&mut self,
+            format: RenderFormat, transparent: Option<u8>,
+            dst: &mut [u8], pitch: usize)
+            -> FlicResult<FlicPlaybackResult> {
+        let mut screen = match self.screen.take() {
+            Some(screen) => screen,
+            None => self.new_frame(),
+        };
+
+        let res = self.read_next_frame_into(&mut screen);
+        let res = res.and_then(|res| {
+            render(&screen.as_raster(), format, transparent, dst, pitch)?;
+            Ok(res)
+        });
+
+        self.screen = Some(screen);
+        res
+    }
+
+    /// The indexed screen used by `read_next_frame_rendered`, if a
+    /// frame has been rendered.
+    pub fn screen(&self) -> Option<&Frame> {
+        self.screen.as_ref()
+    }
+
+    /// Decode the next frame into an owned frame.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// if let Ok(ref mut flic) = flic::FlicFile::open(Path::new("ex.fli")) {
+    ///     let mut frame = flic.new_frame();
+    ///     let res = flic.read_next_frame_into(&mut frame);
+    /// }
+    /// ```
+    pub fn read_next_frame_into(&mut self, dst: &mut Frame)
+            -> FlicResult<FlicPlaybackResult> {
+        self.read_next_frame(&mut dst.as_raster_mut())
+    }
 }
 
 /*--------------------------------------------------------------*/
@@ -433,7 +861,7 @@ impl FlicFileWriter {
         // Reserve space for header.
         file.write_all(&[0; SIZE_OF_FLIC_HEADER])?;
 
-        let jiffy_speed = min((speed_msec as u64) * 70 / 1000, ::std::u16::MAX as u64) as u16;
+        let jiffy_speed = msec_to_jiffies(speed_msec);
 
         let hdr = FlicHeader {
             magic: FLIHR_MAGIC,
@@ -455,6 +883,14 @@ impl FlicFileWriter {
             hdr: hdr,
             offset_frame1: 0,
             offset_frame2: 0,
+            encoder: FrameEncoder::new(FLIHR_MAGIC)?,
+            pixel_tolerance: 0,
+            rate: None,
+            track_frames: false,
+            append: false,
+            state: None,
+            summary: EncodeSummary::default(),
+            timing: None,
             filename: filename.to_path_buf(),
             file: Some(file),
         })
@@ -484,7 +920,7 @@ impl FlicFileWriter {
             frame_count: 0,
             w: 320,
             h: 200,
-            speed_msec: (speed_jiffies as u32) * 1000 / 70,
+            speed_msec: jiffies_to_msec(speed_jiffies),
             speed_jiffies: speed_jiffies,
             created: 0,
             creator: 0,
@@ -498,6 +934,81 @@ impl FlicFileWriter {
             hdr: hdr,
             offset_frame1: 0,
             offset_frame2: 0,
+            encoder: FrameEncoder::new(FLIH_MAGIC)?,
+            pixel_tolerance: 0,
+            rate: None,
+            track_frames: false,
+            append: false,
+            state: None,
+            summary: EncodeSummary::default(),
+            timing: None,
+            filename: filename.to_path_buf(),
+            file: Some(file),
+        })
+    }
+
+    /// Open an existing FLI or FLC file for appending frames.
+    ///
+    /// The existing frames are decoded to find the last frame, which
+    /// becomes the previous frame for the next call to
+    /// `write_next_frame`.  The old ring frame is removed.
+    ///
+    /// Unlike a newly created FLIC, you must not supply the ring
+    /// frame.  It is generated from the first frame when the FLIC
+    /// writer is closed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// flic::FlicFileWriter::append(Path::new("ex.flc"));
+    /// ```
+    pub fn append(filename: &Path)
+            -> FlicResult<Self> {
+        let mut flic = FlicFile::open(filename)?;
+        let w = flic.width() as usize;
+        let h = flic.height() as usize;
+        let frame_count = flic.frame_count() as usize;
+
+        let mut state = FlicWriterState::new(w, h);
+
+        for frame_num in 0..frame_count {
+            flic.read_next_frame(&mut RasterMut::new(
+                    w, h, &mut state.last_buf, &mut state.last_pal))?;
+
+            if frame_num == 0 {
+                state.first_buf.copy_from_slice(&state.last_buf);
+                state.first_pal.copy_from_slice(&state.last_pal);
+            }
+        }
+
+        let FlicFile { mut hdr, frame_hdr, .. } = flic;
+        let offset_frame1 = frame_hdr[0].offset;
+        let offset_frame2 = if frame_count >= 2 { frame_hdr[1].offset } else { 0 };
+        let offset_ring = frame_hdr[frame_count].offset;
+
+        // Remove the old ring frame.
+        let mut file = OpenOptions::new().read(true).write(true).open(filename)?;
+        file.set_len(offset_ring)?;
+        file.seek(SeekFrom::Start(offset_ring))?;
+
+        hdr.updated = 0;
+        hdr.updater = LIBFLIC_UPDATER_ID;
+        let encoder = FrameEncoder::new(hdr.magic)?;
+
+        Ok(FlicFileWriter{
+            hdr: hdr,
+            offset_frame1: offset_frame1,
+            offset_frame2: offset_frame2,
+            encoder: encoder,
+            pixel_tolerance: 0,
+            rate: None,
+            track_frames: true,
+            append: true,
+            state: Some(state),
+            summary: EncodeSummary::default(),
+            timing: None,
             filename: filename.to_path_buf(),
             file: Some(file),
         })
@@ -529,15 +1040,143 @@ impl FlicFileWriter {
         }
     }
 
+    /// Set the compression level.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::flic::CompressionLevel;
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), 320, 200, 70) {
+    ///     flic.set_compression_level(CompressionLevel::Max);
+    /// }
+    /// ```
+    pub fn set_compression_level(&mut self, level: CompressionLevel) {
+        self.encoder.set_compression_level(level);
+    }
+
+    /// Set the pixel tolerance for near-lossless encoding.
+    ///
+    /// Pixels whose colour is within the given Euclidean distance in
+    /// RGB of the previously encoded pixel are treated as unchanged,
+    /// and short isolated changes within twice the distance are
+    /// dropped.  This can greatly reduce the size of noisy or
+    /// dithered animations.  Each frame is compared against the
+    /// frame as it will be decoded, so errors do not accumulate.
+    /// Palettes, the first frame, and frames identical to the first
+    /// frame (e.g. the ring frame) are always encoded losslessly.
+    ///
+    /// A tolerance of 0 (the default) gives lossless encoding.
+    ///
+    /// The tolerance must be set before writing the first frame,
+    /// unless the FLIC writer was opened with `append`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), 320, 200, 70) {
+    ///     flic.set_pixel_tolerance(8);
+    /// }
+    /// ```
+    pub fn set_pixel_tolerance(&mut self, tolerance: u16)
+            -> FlicResult<()> {
+        if self.hdr.frame_count > 0 && self.state.is_none() {
+            return Err(FlicError::BadInput);
+        }
+
+        self.pixel_tolerance = tolerance;
+        self.track_frames = true;
+        Ok(())
+    }
+
+    /// Enable rate-controlled encoding.
+    ///
+    /// The FLIC writer will try to keep the file within the given
+    /// budget by raising the pixel tolerance (a minimum tolerance
+    /// may be set by calling `set_pixel_tolerance` first),
+    /// postponing keyframes, and dropping frames, i.e. writing empty
+    /// frames, as a last resort.  The first frame and frames
+    /// identical to the first frame (e.g. the ring frame) are always
+    /// encoded losslessly, so very small budgets may be exceeded.
+    ///
+    /// A keyframe is a frame encoded without reference to the
+    /// previous frame.  If `keyframe_interval` is non-zero, a
+    /// keyframe is attempted every `keyframe_interval` frames.
+    ///
+    /// Rate control must be enabled before writing the first frame,
+    /// unless the FLIC writer was opened with `append`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::ratectl::RateBudget;
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), 320, 200, 70) {
+    ///     flic.set_rate_control(RateBudget::BytesPerSecond(150 * 1024), 0);
+    /// }
+    /// ```
+    pub fn set_rate_control(&mut self, budget: RateBudget, keyframe_interval: u16)
+            -> FlicResult<()> {
+        if self.hdr.frame_count > 0 && self.state.is_none() {
+            return Err(FlicError::BadInput);
+        }
+
+        self.rate = Some(RateControl::new(
+                budget, self.hdr.speed_msec, keyframe_interval, self.pixel_tolerance));
+        self.track_frames = true;
+        Ok(())
+    }
+
+    /// Get the achieved size and quality of rate-controlled encoding
+    /// so far, or None if rate control is not enabled.
+    ///
+    /// The size includes the header and every frame written so far.
+    pub fn rate_control_report(&self)
+            -> FlicResult<Option<RateControlReport>> {
+        if let (Some(mut file), Some(rate)) = (self.file.as_ref(), self.rate.as_ref()) {
+            let size = file.seek(SeekFrom::Current(0))?;
+            Ok(Some(rate.report(size)))
+        } else {
+            Ok(None)
+        }
+    }
+
     /// Close the FLIC file.
     ///
     /// You must close the FLIC writer after you have supplied all the
     /// frames, including the ring frame, to write out the header.
     ///
     /// The FLIC writer is not usable after being closed.
+    ///
+    /// If the FLIC writer was opened with `append`, the ring frame is
+    /// written here.
+    ///
+    /// Returns a summary of how the frames were encoded.
     pub fn close(mut self)
-            -> FlicResult<()> {
+            -> FlicResult<EncodeSummary> {
         if let Some(mut file) = self.file.take() {
+            if let (true, Some(state)) = (self.append, self.state.take()) {
+                let w = self.hdr.w as usize;
+                let h = self.hdr.h as usize;
+                if self.hdr.frame_count == ::std::u16::MAX {
+                    return Err(FlicError::ExceededLimit);
+                }
+
+                self.encoder.write_frame(self.hdr.frame_count,
+                        Some(&Raster::new(w, h, &state.last_buf, &state.last_pal)),
+                        &Raster::new(w, h, &state.first_buf, &state.first_pal),
+                        &mut file)?;
+                self.summary.record(self.encoder.last_frame_info());
+                self.hdr.frame_count = self.hdr.frame_count + 1;
+            }
+
             if self.hdr.frame_count == 0 {
                 return Err(FlicError::Corrupted);
             } else if self.hdr.frame_count == 1 {
@@ -558,7 +1197,7 @@ impl FlicFileWriter {
                     &self.hdr, self.offset_frame1, self.offset_frame2,
                     &mut file)?;
 
-            Ok(())
+            Ok(::std::mem::replace(&mut self.summary, EncodeSummary::default()))
         } else {
             Err(FlicError::NoFile)
         }
@@ -571,6 +1210,12 @@ impl FlicFileWriter {
     /// animation, you must also supply the first frame to create the
     /// ring frame.
     ///
+    /// When appending to an existing FLIC, or when a pixel tolerance
+    /// is set, the previous frame is tracked by the FLIC writer and
+    /// the supplied previous frame is ignored.
+    ///
+    /// Returns which chunks were written for the frame.
+    ///
     /// # Examples
     ///
     /// ```no_run
@@ -597,29 +1242,701 @@ impl FlicFileWriter {
     /// }
     /// ```
     pub fn write_next_frame(&mut self, prev: Option<&Raster>, next: &Raster)
-            -> FlicResult<()> {
-        if let Some(mut file) = self.file.as_ref() {
-            if (next.w != self.hdr.w as usize) || (next.h != self.hdr.h as usize) {
-                return Err(FlicError::WrongResolution);
-            }
-            if self.hdr.frame_count == ::std::u16::MAX {
-                return Err(FlicError::ExceededLimit);
-            }
+            -> FlicResult<FrameEncodeInfo> {
+        self.write_next_frame_hinted(prev, next, None)
+    }
 
-            if self.hdr.frame_count == 0 {
-                self.offset_frame1 = file.seek(SeekFrom::Current(0))?;
-            } else if self.hdr.frame_count == 1 {
-                self.offset_frame2 = file.seek(SeekFrom::Current(0))?;
+    /// Allocate a frame of the FLIC's size.
+    pub fn new_frame(&self) -> Frame {
+        Frame::new(self.hdr.w as usize, self.hdr.h as usize)
+    }
+
+    /// Encode the next owned frame in the FLIC.
+    ///
+    /// This is the same as `write_next_frame`.
+    pub fn write_next_frame_from(&mut self, prev: Option<&Frame>, next: &Frame)
+            -> FlicResult<FrameEncodeInfo> {
+        let prev = prev.map(|p| p.as_raster());
+        self.write_next_frame(prev.as_ref(), &next.as_raster())
+    }
+
+    /// Encode the next frame in the FLIC, given the regions that
+    /// changed since the previous frame.
+    ///
+    /// Only the pixels in the changed regions are compared when
+    /// building delta chunks; all other pixels are assumed to be
+    /// unchanged.  Hints are ignored when encoding with a pixel
+    /// tolerance or rate control.  Otherwise this is the same as
+    /// `write_next_frame`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::Rect;
+    /// use flic::codec::ChangeHint;
+    ///
+    /// const SCREEN_W: usize = 320;
+    /// const SCREEN_H: usize = 200;
+    /// let buf1 = [0; SCREEN_W * SCREEN_H];
+    /// let mut buf2 = [0; SCREEN_W * SCREEN_H];
+    /// buf2[SCREEN_W * 10 + 20] = 1;
+    /// let pal = [0; 3 * 256];
+    /// let raster1 = flic::Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+    /// let raster2 = flic::Raster::new(SCREEN_W, SCREEN_H, &buf2, &pal);
+    /// let dirty = [ Rect { x: 20, y: 10, w: 1, h: 1 } ];
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), SCREEN_W as u16, SCREEN_H as u16, 70) {
+    ///     flic.write_next_frame(None, &raster1);
+    ///     flic.write_next_frame_with_hint(Some(&raster1), &raster2, &ChangeHint::Rects(&dirty));
+    ///     flic.write_next_frame_with_hint(Some(&raster2), &raster1, &ChangeHint::Rects(&dirty));
+    ///     flic.close();
+    /// }
+    /// ```
+    pub fn write_next_frame_with_hint(&mut self,
+            prev: Option<&Raster>, next: &Raster, hint: &ChangeHint)
+            -> FlicResult<FrameEncodeInfo> {
+        self.write_next_frame_hinted(prev, next, Some(hint))
+    }
+
+    /// Set whether change hints are checked against a full compare
+    /// of the frames.  Hints that miss a change are rejected with
+    /// `BadInput`.  This is on by default in debug builds.
+    pub fn set_verify_change_hints(&mut self, verify: bool) {
+        self.encoder.set_verify_hints(verify);
+    }
+
+    /// Set the size and filtering of the postage stamp written with
+    /// the first frame.  FLI files do not have postage stamps.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::pstamp::{PstampFilter,PstampOptions};
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), 640, 480, 70) {
+    ///     flic.set_pstamp_options(PstampOptions {
+    ///         max_w: 160,
+    ///         max_h: 120,
+    ///         filter: PstampFilter::Average,
+    ///         dither: true,
+    ///     });
+    /// }
+    /// ```
+    pub fn set_pstamp_options(&mut self, options: PstampOptions) {
+        self.encoder.set_pstamp_options(options);
+    }
+
+    /// Encode the next frame in the FLIC, and hold it for roughly the
+    /// given duration by following it with empty frames.
+    ///
+    /// The FLIC's speed is the base tick, and each frame is held for
+    /// a whole number of ticks, at least one.  The rounding error is
+    /// carried over to the following frames, so the total playback
+    /// time stays close to the sum of the durations.  A base tick
+    /// can be picked with `timing::choose_speed_msec`.
+    ///
+    /// With rate control enabled, the empty frames are recorded with
+    /// the rate controller like any other frame, so their unused
+    /// share of the budget is left for the frames that follow.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::timing::choose_speed_msec;
+    ///
+    /// const SCREEN_W: usize = 320;
+    /// const SCREEN_H: usize = 200;
+    /// let buf = [0; SCREEN_W * SCREEN_H];
+    /// let pal = [0; 3 * 256];
+    /// let raster = flic::Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
+    /// let durations = [ 2000, 100, 150 ];
+    /// let speed_msec = choose_speed_msec(&durations, ::std::u16::MAX as u32 - 1);
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), SCREEN_W as u16, SCREEN_H as u16, speed_msec) {
+    ///     flic.write_next_frame_with_duration(None, &raster, durations[0]);
+    ///     flic.write_next_frame_with_duration(Some(&raster), &raster, durations[1]);
+    ///     flic.write_next_frame_with_duration(Some(&raster), &raster, durations[2]);
+    ///     flic.write_next_frame(Some(&raster), &raster);
+    ///     if let Some(report) = flic.timing_report() {
+    ///         println!("Timing error: {} ms", report.total_error_msec);
+    ///     }
+    ///     flic.close();
+    /// }
+    /// ```
+    pub fn write_next_frame_with_duration(&mut self,
+            prev: Option<&Raster>, next: &Raster, duration_msec: u32)
+            -> FlicResult<FrameEncodeInfo> {
+        let mut timing = match self.timing {
+            Some(timing) => timing,
+            None => {
+                let tick_usec = if self.hdr.magic == FLIH_MAGIC {
+                    (self.hdr.speed_jiffies as u64) * 1000000 / 70
+                } else {
+                    (self.hdr.speed_msec as u64) * 1000
+                };
+                FrameTiming::new(tick_usec)
+            },
+        };
+
+        let ticks = timing.next_ticks(duration_msec);
+        if self.hdr.frame_count as u64 + ticks as u64 >= ::std::u16::MAX as u64 {
+            return Err(FlicError::ExceededLimit);
+        }
+
+        let info = self.write_next_frame(prev, next)?;
+
+        let mut buf = Vec::with_capacity(SIZE_OF_FLIC_FRAME);
+        write_empty_frame(&mut buf)?;
+        for _ in 1..ticks {
+            if let (Some(mut file), Some(rate)) = (self.file.as_ref(), self.rate.as_mut()) {
+                let pos = file.seek(SeekFrom::Current(0))?;
+                let available = rate.target_size(self.hdr.frame_count) as i64 - pos as i64;
+                let tolerance = rate.tolerance();
+                rate.record_frame(SIZE_OF_FLIC_FRAME, available, tolerance, false, false);
+                if let Some(ref state) = self.state {
+                    rate.record_error(next, &state.last_buf, &state.last_pal);
+                }
             }
 
-            let prev = if self.hdr.frame_count == 0 {
-                None
+            self.write_encoded_frame(&buf)?;
+            self.summary.record(&FrameEncodeInfo {
+                size: SIZE_OF_FLIC_FRAME,
+                ..FrameEncodeInfo::default()
+            });
+        }
+
+        self.timing = Some(timing);
+        Ok(info)
+    }
+
+    /// Get how closely the frames written with
+    /// `write_next_frame_with_duration` match their durations, or
+    /// None if no such frames were written.
+    pub fn timing_report(&self)
+            -> Option<TimingReport> {
+        self.timing.map(|timing| timing.report())
+    }
+
+    fn write_next_frame_hinted(&mut self,
+            prev: Option<&Raster>, next: &Raster, hint: Option<&ChangeHint>)
+            -> FlicResult<FrameEncodeInfo> {
+        if let Some(mut file) = self.file.as_ref() {
+            if (next.w != self.hdr.w as usize) || (next.h != self.hdr.h as usize) {
+                return Err(FlicError::WrongResolution);
+            }
+            if self.hdr.frame_count == ::std::u16::MAX {
+                return Err(FlicError::ExceededLimit);
+            }
+
+            if self.hdr.frame_count == 0 {
+                self.offset_frame1 = file.seek(SeekFrom::Current(0))?;
+            } else if self.hdr.frame_count == 1 {
+                self.offset_frame2 = file.seek(SeekFrom::Current(0))?;
+            }
+
+            let info = if self.hdr.frame_count == 0 && self.track_frames {
+                let mut state = FlicWriterState::new(next.w, next.h);
+                copy_raster(next, &mut state.first_buf, &mut state.first_pal);
+                copy_raster(next, &mut state.last_buf, &mut state.last_pal);
+
+                let size = self.encoder.write_frame(self.hdr.frame_count,
+                        None, next, &mut file)?;
+
+                if let Some(ref mut rate) = self.rate {
+                    let target = rate.target_size(self.hdr.frame_count);
+                    let available = target as i64 - (self.offset_frame1 as i64);
+                    rate.record_frame(size, available, 0, true, false);
+                    rate.record_error(next, &state.last_buf, &state.last_pal);
+                }
+
+                self.state = Some(state);
+                self.encoder.last_frame_info().clone()
+            } else if let (Some(state), Some(rate)) = (self.state.as_mut(), self.rate.as_mut()) {
+                let pos = file.seek(SeekFrom::Current(0))?;
+                let available = rate.target_size(self.hdr.frame_count) as i64 - pos as i64;
+                let exact = raster_eq(next, &state.first_buf, &state.first_pal);
+                let mut keyframe = false;
+                let mut tolerance = 0;
+                let mut encoded = None;
+
+                // Frames identical to the first frame, e.g. the ring
+                // frame, are always encoded losslessly.
+                if exact {
+                    encoded = Some(encode_tracked_frame(
+                            &mut self.encoder, self.hdr.frame_count, state, next, 0, false, None)?);
+                }
+
+                // Try a keyframe if one is due, but postpone it if it
+                // does not fit in the budget.
+                if encoded.is_none() && rate.want_keyframe() {
+                    let (buf, pix, info) = encode_tracked_frame(
+                            &mut self.encoder, self.hdr.frame_count, state, next, 0, true, None)?;
+                    if (buf.len() as i64) <= available {
+                        keyframe = true;
+                        encoded = Some((buf, pix, info));
+                    }
+                }
+
+                // Raise the tolerance until the frame fits.
+                if encoded.is_none() {
+                    let mut tol = Some(rate.tolerance());
+                    while let Some(t) = tol {
+                        let (buf, pix, info) = encode_tracked_frame(
+                                &mut self.encoder, self.hdr.frame_count, state, next, t, false, None)?;
+                        if (buf.len() as i64) <= available {
+                            tolerance = t;
+                            encoded = Some((buf, pix, info));
+                            break;
+                        }
+
+                        tol = rate.next_tolerance(t);
+                    }
+                }
+
+                // Drop the frame if nothing fits.
+                let info = if let Some((buf, pix, info)) = encoded {
+                    file.write_all(&buf)?;
+                    state.last_buf.copy_from_slice(&pix);
+                    state.last_pal.copy_from_slice(&next.pal);
+                    rate.record_frame(buf.len(), available, tolerance, keyframe, false);
+                    info
+                } else {
+                    write_empty_frame(&mut file)?;
+                    rate.record_frame(SIZE_OF_FLIC_FRAME, available,
+                            MAX_RATE_TOLERANCE, false, true);
+                    FrameEncodeInfo {
+                        size: SIZE_OF_FLIC_FRAME,
+                        dropped: true,
+                        ..FrameEncodeInfo::default()
+                    }
+                };
+
+                rate.record_error(next, &state.last_buf, &state.last_pal);
+                info
+            } else if let Some(ref mut state) = self.state {
+                let tolerance = if raster_eq(next, &state.first_buf, &state.first_pal) {
+                    0
+                } else {
+                    self.pixel_tolerance
+                };
+
+                // Hints are relative to the caller's frames, which only
+                // match the tracked frames when encoding losslessly.
+                let hint = if self.pixel_tolerance == 0 { hint } else { None };
+
+                let (buf, pix, info) = encode_tracked_frame(
+                        &mut self.encoder, self.hdr.frame_count, state, next, tolerance, false, hint)?;
+                file.write_all(&buf)?;
+                state.last_buf.copy_from_slice(&pix);
+                state.last_pal.copy_from_slice(&next.pal);
+                info
             } else {
-                prev
+                let prev = if self.hdr.frame_count == 0 {
+                    None
+                } else {
+                    prev
+                };
+
+                let buf = self.encoder.encode_frame_with_hint(self.hdr.frame_count,
+                        prev, next, hint)?;
+                file.write_all(buf)?;
+                self.encoder.last_frame_info().clone()
             };
 
-            write_next_frame(self.hdr.magic, self.hdr.frame_count,
-                    prev, next, &mut file)?;
+            self.summary.record(&info);
+            self.hdr.frame_count = self.hdr.frame_count + 1;
+
+            Ok(info)
+        } else {
+            Err(FlicError::NoFile)
+        }
+    }
+
+    /// Quantize a truecolor frame to 256 colours and encode it as the
+    /// next frame in the FLIC.
+    ///
+    /// The source image is packed, with the FLIC's width in pixels
+    /// per row.  Each frame gets its own palette.  The previous frame
+    /// is tracked by the FLIC writer, so truecolor frames can be
+    /// freely mixed with frames written by `write_next_frame`.  To
+    /// close the animation, supply the first frame again as the ring
+    /// frame.
+    ///
+    /// Truecolor frames must be written from the first frame, unless
+    /// the FLIC writer was opened with `append`, or a pixel tolerance
+    /// or rate control was set.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::quantize::{Dither,TruecolorFormat};
+    ///
+    /// const SCREEN_W: u16 = 320;
+    /// const SCREEN_H: u16 = 200;
+    /// let rgb = vec![0; 3 * (SCREEN_W as usize) * (SCREEN_H as usize)];
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), SCREEN_W, SCREEN_H, 70) {
+    ///     flic.write_next_frame_rgb(&rgb, TruecolorFormat::Rgb24, Dither::Ordered);
+    ///     flic.write_next_frame_rgb(&rgb, TruecolorFormat::Rgb24, Dither::Ordered);
+    ///     flic.close();
+    /// }
+    /// ```
+    pub fn write_next_frame_rgb(&mut self,
+            src: &[u8], format: TruecolorFormat, dither: Dither)
+            -> FlicResult<FrameEncodeInfo> {
+        self.write_next_frame_truecolor(src, format, dither, None)
+    }
+
+    /// Map a truecolor frame to the given 256-colour palette and
+    /// encode it as the next frame in the FLIC.
+    ///
+    /// This is like `write_next_frame_rgb`, but uses a palette
+    /// planned across frames, e.g. by
+    /// `quantize::make_global_palette` or
+    /// `quantize::plan_sliding_palettes`, which keeps palette and
+    /// pixel changes between frames small.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    /// use flic::quantize::{Dither,TruecolorFormat};
+    ///
+    /// const SCREEN_W: u16 = 320;
+    /// const SCREEN_H: u16 = 200;
+    /// let rgb1 = vec![0; 3 * (SCREEN_W as usize) * (SCREEN_H as usize)];
+    /// let rgb2 = vec![0; 3 * (SCREEN_W as usize) * (SCREEN_H as usize)];
+    /// let mut pal = [0; 3 * 256];
+    /// flic::quantize::make_global_palette(
+    ///         &[&rgb1, &rgb2], TruecolorFormat::Rgb24, 256, &mut pal);
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), SCREEN_W, SCREEN_H, 70) {
+    ///     for rgb in &[&rgb1, &rgb2, &rgb1] {
+    ///         flic.write_next_frame_rgb_with_palette(
+    ///                 rgb, TruecolorFormat::Rgb24, Dither::Ordered, &pal);
+    ///     }
+    ///     flic.close();
+    /// }
+    /// ```
+    pub fn write_next_frame_rgb_with_palette(&mut self,
+            src: &[u8], format: TruecolorFormat, dither: Dither, pal: &[u8])
+            -> FlicResult<FrameEncodeInfo> {
+        if pal.len() != 3 * 256 {
+            return Err(FlicError::BadInput);
+        }
+
+        self.write_next_frame_truecolor(src, format, dither, Some(pal))
+    }
+
+    /// Quantize or remap a truecolor frame, and encode it against
+    /// the tracked previous frame.
+    fn write_next_frame_truecolor(&mut self,
+            src: &[u8], format: TruecolorFormat, dither: Dither, pal: Option<&[u8]>)
+            -> FlicResult<FrameEncodeInfo> {
+        if self.hdr.frame_count > 0 && self.state.is_none() {
+            return Err(FlicError::BadInput);
+        }
+
+        let w = self.hdr.w as usize;
+        let h = self.hdr.h as usize;
+        let mut buf = vec![0; w * h];
+        let mut next_pal = vec![0; 3 * 256];
+        {
+            let mut dst = RasterMut::new(w, h, &mut buf, &mut next_pal);
+            if let Some(pal) = pal {
+                dst.pal.copy_from_slice(pal);
+                remap(src, format, dither, 256, &mut dst)?;
+            } else {
+                quantize(src, format, dither, &mut dst)?;
+            }
+        }
+
+        self.track_frames = true;
+        self.write_next_frame(None, &Raster::new(w, h, &buf, &next_pal))
+    }
+
+    /// Write an undecoded frame chunk, including the frame header,
+    /// as the next frame in the FLIC.
+    ///
+    /// The frame is copied byte-for-byte, so it must have been
+    /// encoded against the previous frame written, e.g. as read by
+    /// `FlicFile::read_raw_frame`.
+    pub fn write_raw_frame(&mut self, buf: &[u8])
+            -> FlicResult<()> {
+        if let Some(mut file) = self.file.as_ref() {
+            if buf.len() < SIZE_OF_FLIC_FRAME {
+                return Err(FlicError::Corrupted);
+            }
+
+            let mut r = Cursor::new(buf);
+            let size = r.read_u32::<LE>()?;
+            let magic = r.read_u16::<LE>()?;
+            let num_chunks = r.read_u16::<LE>()?;
+            if size as usize != buf.len() {
+                return Err(FlicError::Corrupted);
+            } else if magic != FCID_FRAME {
+                return Err(FlicError::BadMagic);
+            }
+
+            if self.hdr.frame_count == ::std::u16::MAX {
+                return Err(FlicError::ExceededLimit);
+            }
+
+            // Keep track of the last frame.
+            if let Some(ref mut state) = self.state {
+                let w = self.hdr.w as usize;
+                let h = self.hdr.h as usize;
+                let mut dst = RasterMut::new(w, h, &mut state.last_buf, &mut state.last_pal);
+                let mut offset = SIZE_OF_FLIC_FRAME;
+
+                for _ in 0..num_chunks {
+                    if offset + SIZE_OF_CHUNK > buf.len() {
+                        return Err(FlicError::Corrupted);
+                    }
+
+                    let mut r = Cursor::new(&buf[offset..]);
+                    let chunk_size = r.read_u32::<LE>()? as usize;
+                    let chunk_magic = r.read_u16::<LE>()?;
+                    if chunk_size < SIZE_OF_CHUNK || offset + chunk_size > buf.len() {
+                        return Err(FlicError::Corrupted);
+                    }
+
+                    decode_chunk(chunk_magic,
+                            &buf[(offset + SIZE_OF_CHUNK)..(offset + chunk_size)], &mut dst)?;
+                    offset = offset + chunk_size;
+                }
+            }
+
+            if self.hdr.frame_count == 0 {
+                self.offset_frame1 = file.seek(SeekFrom::Current(0))?;
+            } else if self.hdr.frame_count == 1 {
+                self.offset_frame2 = file.seek(SeekFrom::Current(0))?;
+            }
+
+            file.write_all(buf)?;
+            self.summary.record_raw(buf.len());
+            self.hdr.frame_count = self.hdr.frame_count + 1;
+
+            Ok(())
+        } else {
+            Err(FlicError::NoFile)
+        }
+    }
+
+    /// Encode a sequence of frames using worker threads, and write
+    /// them in order as the next frames in the FLIC.
+    ///
+    /// Each frame is encoded against the frame before it, which must
+    /// be supplied in `prev` for the first frame in the sequence, as
+    /// in `write_next_frame`.  If `num_threads` is 0, one thread per
+    /// available CPU is used.  At most twice as many frames as
+    /// threads are encoded ahead of the next frame to be written.
+    ///
+    /// Pixel tolerance and rate control make each frame depend on
+    /// how the previous frame was encoded, so they fall back to
+    /// encoding the frames one at a time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// const SCREEN_W: usize = 320;
+    /// const SCREEN_H: usize = 200;
+    /// let bufs = vec![vec![0; SCREEN_W * SCREEN_H]; 10];
+    /// let pal = [0; 3 * 256];
+    /// let frames: Vec<flic::Raster> = bufs.iter()
+    ///     .chain(bufs.iter().take(1))
+    ///     .map(|buf| flic::Raster::new(SCREEN_W, SCREEN_H, buf, &pal))
+    ///     .collect();
+    ///
+    /// if let Ok(mut flic) = flic::FlicFileWriter::create(
+    ///         Path::new("ex.flc"), SCREEN_W as u16, SCREEN_H as u16, 70) {
+    ///     flic.write_frames(None, &frames, 0);
+    ///     flic.close();
+    /// }
+    /// ```
+    pub fn write_frames(&mut self,
+            prev: Option<&Raster>, frames: &[Raster], num_threads: usize)
+            -> FlicResult<()> {
+        if self.file.is_none() {
+            return Err(FlicError::NoFile);
+        }
+
+        let w = self.hdr.w as usize;
+        let h = self.hdr.h as usize;
+        if frames.iter().any(|f| f.w != w || f.h != h) {
+            return Err(FlicError::WrongResolution);
+        }
+        if self.hdr.frame_count as usize + frames.len() > ::std::u16::MAX as usize {
+            return Err(FlicError::ExceededLimit);
+        }
+
+        if self.pixel_tolerance > 0 || self.rate.is_some() {
+            let mut prev = prev;
+            for next in frames {
+                self.write_next_frame(prev, next)?;
+                prev = Some(next);
+            }
+            return Ok(());
+        }
+
+        let num_threads = if num_threads > 0 {
+            num_threads
+        } else {
+            thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+        };
+
+        let flic_magic = self.hdr.magic;
+        let frame_count = self.hdr.frame_count;
+        let level = self.encoder.level;
+        let pstamp = self.encoder.pstamp;
+
+        // The previous frame of the first frame in the sequence.
+        let last = self.state.as_ref().map(|s| (s.last_buf.clone(), s.last_pal.clone()));
+        let first_prev = if frame_count == 0 {
+            None
+        } else if let Some((ref buf, ref pal)) = last {
+            Some(Raster::new(w, h, buf, pal))
+        } else {
+            prev.map(|p| Raster::with_offset(p.x, p.y, p.w, p.h, p.stride, p.buf, p.pal))
+        };
+
+        // Workers wait for the frames to be written before encoding
+        // too far ahead, so a slow frame cannot leave an unbounded
+        // number of encoded frames waiting in memory.
+        let window = 2 * num_threads;
+        let written = (Mutex::new(0), Condvar::new());
+
+        let next_job = AtomicUsize::new(0);
+        let abort = AtomicBool::new(false);
+        let (tx, rx) = mpsc::channel();
+
+        let stop = || {
+            abort.store(true, Ordering::Relaxed);
+            let _lock = written.0.lock();
+            written.1.notify_all();
+        };
+
+        let res = thread::scope(|scope| {
+            for _ in 0..num_threads {
+                let tx = tx.clone();
+                let next_job = &next_job;
+                let abort = &abort;
+                let written = &written;
+                let first_prev = &first_prev;
+
+                scope.spawn(move || {
+                    let mut encoder = match FrameEncoder::new(flic_magic) {
+                        Ok(encoder) => encoder,
+                        Err(e) => {
+                            let _ = tx.send((0, Err(e)));
+                            return;
+                        },
+                    };
+                    encoder.set_compression_level(level);
+                    encoder.set_pstamp_options(pstamp);
+
+                    while !abort.load(Ordering::Relaxed) {
+                        let i = next_job.fetch_add(1, Ordering::Relaxed);
+                        if i >= frames.len() {
+                            break;
+                        }
+
+                        if let Ok(mut n) = written.0.lock() {
+                            while i >= *n + window && !abort.load(Ordering::Relaxed) {
+                                n = match written.1.wait(n) {
+                                    Ok(n) => n,
+                                    Err(_) => return,
+                                };
+                            }
+                        }
+
+                        if abort.load(Ordering::Relaxed) {
+                            break;
+                        }
+
+                        let prev = if i == 0 { first_prev.as_ref() } else { Some(&frames[i - 1]) };
+                        let res = encoder.encode_frame(frame_count + i as u16, prev, &frames[i])
+                            .map(|buf| buf.to_vec())
+                            .map(|buf| (buf, encoder.last_frame_info().clone()));
+
+                        if tx.send((i, res)).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+
+            drop(tx);
+
+            // Write the frames in order as they arrive.
+            let mut pending = BTreeMap::new();
+            let mut next_write = 0;
+            for (i, res) in rx {
+                match res {
+                    Ok(buf) => { pending.insert(i, buf); },
+                    Err(e) => {
+                        stop();
+                        return Err(e);
+                    },
+                }
+
+                while let Some((buf, info)) = pending.remove(&next_write) {
+                    if let Err(e) = self.write_encoded_frame(&buf) {
+                        stop();
+                        return Err(e);
+                    }
+                    self.summary.record(&info);
+                    next_write = next_write + 1;
+                }
+
+                if let Ok(mut n) = written.0.lock() {
+                    *n = next_write;
+                    written.1.notify_all();
+                }
+            }
+
+            Ok(())
+        });
+
+        res?;
+
+        if frame_count == 0 && self.track_frames && self.state.is_none() {
+            if let Some(first) = frames.first() {
+                let mut state = FlicWriterState::new(w, h);
+                copy_raster(first, &mut state.first_buf, &mut state.first_pal);
+                self.state = Some(state);
+            }
+        }
+
+        if let (Some(state), Some(last)) = (self.state.as_mut(), frames.last()) {
+            copy_raster(last, &mut state.last_buf, &mut state.last_pal);
+        }
+
+        Ok(())
+    }
+
+    /// Write an encoded frame, keeping track of the frame offsets.
+    fn write_encoded_frame(&mut self, buf: &[u8])
+            -> FlicResult<()> {
+        if let Some(mut file) = self.file.as_ref() {
+            if self.hdr.frame_count == 0 {
+                self.offset_frame1 = file.seek(SeekFrom::Current(0))?;
+            } else if self.hdr.frame_count == 1 {
+                self.offset_frame2 = file.seek(SeekFrom::Current(0))?;
+            }
+
+            file.write_all(buf)?;
             self.hdr.frame_count = self.hdr.frame_count + 1;
 
             Ok(())
@@ -629,6 +1946,94 @@ impl FlicFileWriter {
     }
 }
 
+/*--------------------------------------------------------------*/
+
+impl FlicHeaderEditor {
+    /// Open a FLIC file for editing its header.
+    ///
+    /// The whole file is validated before it is opened for writing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::path::Path;
+    ///
+    /// if let Ok(mut editor) = flic::FlicHeaderEditor::open(Path::new("ex.flc")) {
+    ///     editor.set_speed_msec(50);
+    ///     editor.close();
+    /// }
+    /// ```
+    pub fn open(filename: &Path)
+            -> FlicResult<Self> {
+        let FlicFile { hdr, frame_hdr, .. } = FlicFile::open(filename)?;
+        let file = OpenOptions::new().read(true).write(true).open(filename)?;
+
+        Ok(FlicHeaderEditor {
+            hdr: hdr,
+            offset_frame1: frame_hdr[0].offset,
+            offset_frame2: frame_hdr[1].offset,
+            file: file,
+        })
+    }
+
+    /// Set the number of milliseconds to delay between each frame
+    /// during playback.
+    ///
+    /// FLI files store the speed in jiffies, so the speed will be
+    /// rounded down to the nearest jiffy.
+    pub fn set_speed_msec(&mut self, speed_msec: u32) {
+        self.hdr.speed_msec = speed_msec;
+        self.hdr.speed_jiffies = msec_to_jiffies(speed_msec);
+    }
+
+    /// Set the number of jiffies to delay between each frame during
+    /// playback.  A jiffy is 1/70 of a second.
+    pub fn set_speed_jiffies(&mut self, speed_jiffies: u16) {
+        self.hdr.speed_msec = jiffies_to_msec(speed_jiffies);
+        self.hdr.speed_jiffies = speed_jiffies;
+    }
+
+    /// Set the FLIC creator and creation time.
+    ///
+    /// FLI files do not store the creator.
+    pub fn set_creator(&mut self, creator: u32, created: u32) {
+        self.hdr.creator = creator;
+        self.hdr.created = created;
+    }
+
+    /// Set the most recent updater and update time.
+    ///
+    /// FLI files do not store the updater.
+    pub fn set_updater(&mut self, updater: u32, updated: u32) {
+        self.hdr.updater = updater;
+        self.hdr.updated = updated;
+    }
+
+    /// Set the aspect ratio, i.e. x by y is a square.
+    ///
+    /// FLI files do not store the aspect ratio; it is always 6:5.
+    pub fn set_aspect_ratio(&mut self, x: u16, y: u16) {
+        if x > 0 && y > 0 {
+            self.hdr.aspect_x = x;
+            self.hdr.aspect_y = y;
+        } else {
+            self.hdr.aspect_x = 1;
+            self.hdr.aspect_y = 1;
+        }
+    }
+
+    /// Write the header and close the FLIC file.
+    pub fn close(mut self)
+            -> FlicResult<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        write_flic_header(
+                &self.hdr, self.offset_frame1, self.offset_frame2,
+                &mut self.file)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
 impl Drop for FlicFileWriter {
     /// A method called when the value goes out of scope.
     fn drop(&mut self) {
@@ -641,6 +2046,16 @@ impl Drop for FlicFileWriter {
 
 /*--------------------------------------------------------------*/
 
+/// Convert a speed in milliseconds to jiffies, rounding down.
+fn msec_to_jiffies(speed_msec: u32) -> u16 {
+    min((speed_msec as u64) * 70 / 1000, ::std::u16::MAX as u64) as u16
+}
+
+/// Convert a speed in jiffies to milliseconds, rounding down.
+fn jiffies_to_msec(speed_jiffies: u16) -> u32 {
+    (speed_jiffies as u32) * 1000 / 70
+}
+
 /// Read the FLIC's header.
 fn read_flic_header(file: &mut File)
         -> FlicResult<FlicHeader> {
@@ -690,7 +2105,7 @@ fn read_fli_header(
         frame_count: frame_count,
         w: width,
         h: height,
-        speed_msec: (jiffy_speed as u32) * 1000 / 70,
+        speed_msec: jiffies_to_msec(jiffy_speed),
         speed_jiffies: jiffy_speed,
         created: 0,
         creator: 0,
@@ -733,7 +2148,7 @@ fn read_flc_header(
         return Err(FlicError::Corrupted);
     }
 
-    let jiffy_speed = min((speed as u64) * 70 / 1000, ::std::u16::MAX as u64) as u16;
+    let jiffy_speed = msec_to_jiffies(speed);
 
     if aspect_x <= 0 || aspect_y <= 0 {
         aspect_x = 1;
@@ -826,6 +2241,8 @@ fn read_frame_headers(file: &mut File, hdr: &FlicHeader)
         }
 
         frames.push(FlicFrame{
+            offset: offset,
+            size: size,
             chunks: chunks,
         });
 
@@ -895,13 +2312,76 @@ fn read_chunk_headers(file: &mut File, hdr: &FlicHeader,
             magic: magic,
         });
 
-        offset = offset + size as u64;
+        offset = offset + size as u64;
+    }
+
+    Ok(chunks)
+}
+
+/*--------------------------------------------------------------*/
+
+/// Copy the raster's pixels and palette into packed buffers.
+fn copy_raster(src: &Raster, buf: &mut [u8], pal: &mut [u8]) {
+    let start = src.stride * src.y;
+    let end = src.stride * (src.y + src.h);
+    for (src_row, dst_row) in src.buf[start..end].chunks(src.stride)
+            .zip(buf.chunks_mut(src.w)) {
+        dst_row.copy_from_slice(&src_row[src.x..(src.x + src.w)]);
+    }
+
+    pal.copy_from_slice(&src.pal);
+}
+
+impl FlicWriterState {
+    /// Allocate the tracked frames.
+    fn new(w: usize, h: usize)
+            -> Self {
+        FlicWriterState {
+            first_buf: vec![0; w * h],
+            first_pal: vec![0; 3 * 256],
+            last_buf: vec![0; w * h],
+            last_pal: vec![0; 3 * 256],
+        }
+    }
+}
+
+/// Encode the next frame into memory, against the last tracked frame.
+///
+/// Returns the encoded frame, the frame's pixels as they will be
+/// decoded, and how the frame was encoded.  Keyframes are encoded
+/// without reference to the last frame.
+fn encode_tracked_frame(
+        encoder: &mut FrameEncoder, frame_count: u16,
+        state: &FlicWriterState, next: &Raster, tolerance: u16, keyframe: bool,
+        hint: Option<&ChangeHint>)
+        -> FlicResult<(Vec<u8>, Vec<u8>, FrameEncodeInfo)> {
+    let prev = Raster::new(next.w, next.h, &state.last_buf, &state.last_pal);
+    let mut pix = vec![0; next.w * next.h];
+    if tolerance > 0 && !keyframe {
+        apply_pixel_tolerance(&prev, next, tolerance, &mut pix)?;
+    } else {
+        let mut pal = [0; 3 * 256];
+        copy_raster(next, &mut pix, &mut pal);
     }
 
-    Ok(chunks)
+    let buf = encoder.encode_frame_with_hint(frame_count,
+            if keyframe { None } else { Some(&prev) },
+            &Raster::new(next.w, next.h, &pix, &next.pal),
+            if keyframe { None } else { hint })?
+        .to_vec();
+    Ok((buf, pix, encoder.last_frame_info().clone()))
 }
 
-/*--------------------------------------------------------------*/
+/// Returns true if the raster's pixels and palette are equal to the
+/// packed buffers.
+fn raster_eq(src: &Raster, buf: &[u8], pal: &[u8])
+        -> bool {
+    let start = src.stride * src.y;
+    let end = src.stride * (src.y + src.h);
+    src.pal == pal
+        && src.buf[start..end].chunks(src.stride).zip(buf.chunks(src.w))
+            .all(|(src_row, row)| &src_row[src.x..(src.x + src.w)] == row)
+}
 
 /// Write the FLIC header.
 fn write_flic_header<W: Write + Seek>(
@@ -975,200 +2455,462 @@ fn write_empty_frame<W: Write>(
     Ok(())
 }
 
-/// Write the next frame.
-fn write_next_frame<W: Write + Seek>(
-        flic_magic: u16, frame_count: u16,
-        prev: Option<&Raster>, next: &Raster, w: &mut W)
-        -> FlicResult<usize> {
-    let pos0 = w.seek(SeekFrom::Current(0))?;
-
-    // Reserve space for chunk.
-    w.write_all(&[0; SIZE_OF_FLIC_FRAME])?;
-
-    let size_pstamp =
-        if flic_magic != FLIH_MAGIC && frame_count == 0 {
-            match write_pstamp_data(next, w) {
-                Ok(size) => size,
-                Err(_) => {
-                    w.seek(SeekFrom::Start(pos0 + SIZE_OF_FLIC_FRAME as u64))?;
-                    0
-                },
-            }
-        } else {
-            0
-        };
+impl EncodeSummary {
+    /// Add an encoded frame to the summary.
+    pub fn record(&mut self, info: &FrameEncodeInfo) {
+        self.frames = self.frames + 1;
+        self.size = self.size + info.size as u64;
+
+        if info.dropped {
+            self.dropped_frames = self.dropped_frames + 1;
+        }
+        if info.palette {
+            self.palette_chunks = self.palette_chunks + 1;
+        }
+        if info.pstamp {
+            self.pstamps = self.pstamps + 1;
+        }
 
-    let size_col = write_color_data(flic_magic, prev, next, w)?;
-    let size_pix = write_pixel_data(flic_magic, prev, next, w)?;
-    let size = SIZE_OF_FLIC_FRAME + size_pstamp + size_col + size_pix;
+        for c in info.chunks.iter() {
+            let stats = self.chunks.entry(c.magic).or_insert(ChunkStats::default());
+            stats.count = stats.count + 1;
+            stats.size = stats.size + c.size as u64;
+        }
 
-    if size > ::std::u32::MAX as usize {
-        return Err(FlicError::ExceededLimit);
+        for c in info.rejected.iter() {
+            let stats = self.rejected.entry(c.magic).or_insert(ChunkStats::default());
+            stats.count = stats.count + 1;
+            stats.size = stats.size + c.size as u64;
+        }
     }
 
-    let pos1 = w.seek(SeekFrom::Current(0))?;
+    /// Add a frame copied without encoding to the summary.
+    fn record_raw(&mut self, size: usize) {
+        self.frames = self.frames + 1;
+        self.raw_frames = self.raw_frames + 1;
+        self.size = self.size + size as u64;
+    }
+}
 
-    w.seek(SeekFrom::Start(pos0))?;
-    if size > 0 {
-        let num_chunks
-            = if size_pstamp > 0 { 1 } else { 0 }
-            + if size_col > 0 { 1 } else { 0 }
-            + if size_pix > 0 { 1 } else { 0 };
+impl fmt::Display for EncodeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} frames, {} bytes", self.frames, self.size)?;
+        if self.dropped_frames > 0 {
+            write!(f, ", {} dropped", self.dropped_frames)?;
+        }
+        if self.raw_frames > 0 {
+            write!(f, ", {} copied", self.raw_frames)?;
+        }
+        write!(f, ", {} palette chunks, {} postage stamps",
+                self.palette_chunks, self.pstamps)?;
 
-        assert_eq!(size, (pos1 - pos0) as usize);
-        w.write_u32::<LE>(size as u32)?;
-        w.write_u16::<LE>(FCID_FRAME)?;
-        w.write_u16::<LE>(num_chunks)?;
-        w.seek(SeekFrom::Start(pos1))?;
-        Ok(size)
-    } else {
-        Ok(0)
+        for (&magic, stats) in self.chunks.iter() {
+            write!(f, "\n  {}: {} chunks, {} bytes",
+                    chunk_name(magic), stats.count, stats.size)?;
+        }
+
+        for (&magic, stats) in self.rejected.iter() {
+            write!(f, "\n  {} (rejected): {} chunks, {} bytes",
+                    chunk_name(magic), stats.count, stats.size)?;
+        }
+
+        Ok(())
     }
 }
 
-/// Write the next frame's palette.
-fn write_color_data<W: Write + Seek>(
-        flic_magic: u16, prev: Option<&Raster>, next: &Raster, w: &mut W)
-        -> FlicResult<usize> {
-    let pos0 = w.seek(SeekFrom::Current(0))?;
+/// The name of a frame chunk type.
+fn chunk_name(magic: u16)
+        -> &'static str {
+    match magic {
+        FLI_WRUN => "FLI_WRUN",
+        FLI_COLOR256 => "FLI_COLOR256",
+        FLI_SS2 => "FLI_SS2",
+        FLI_SBSRSC => "FLI_SBSRSC",
+        FLI_COLOR64 => "FLI_COLOR64",
+        FLI_LC => "FLI_LC",
+        FLI_BLACK => "FLI_BLACK",
+        FLI_ICOLORS => "FLI_ICOLORS",
+        FLI_BRUN => "FLI_BRUN",
+        FLI_COPY => "FLI_COPY",
+        FLI_PSTAMP => "FLI_PSTAMP",
+        _ => "unknown",
+    }
+}
 
-    // Reserve space for chunk.
-    w.write_all(&[0; SIZE_OF_CHUNK])?;
+impl FrameEncoder {
+    /// Create a frame encoder for the given FLIC magic, either
+    /// `FLIH_MAGIC` or `FLIHR_MAGIC`.
+    pub fn new(flic_magic: u16)
+            -> FlicResult<Self> {
+        if flic_magic != FLIH_MAGIC && flic_magic != FLIHR_MAGIC {
+            return Err(FlicError::BadMagic);
+        }
 
-    let (chunk_size, chunk_magic) =
-        if flic_magic == FLIH_MAGIC {
-            let size = encode_fli_color64(prev, next, w)?;
-            (size, FLI_COLOR64)
-        } else {
-            let size = encode_fli_color256(prev, next, w)?;
-            (size, FLI_COLOR256)
-        };
+        Ok(FrameEncoder {
+            flic_magic: flic_magic,
+            level: CompressionLevel::Fast,
+            frame: Vec::new(),
+            best: Cursor::new(Vec::new()),
+            trial: Cursor::new(Vec::new()),
+            info: FrameEncodeInfo::default(),
+            changed_spans: None,
+            verify_hints: cfg!(debug_assertions),
+            pstamp: PstampOptions::default(),
+        })
+    }
 
-    if SIZE_OF_CHUNK + chunk_size > ::std::u32::MAX as usize {
-        return Err(FlicError::ExceededLimit);
+    /// Set the compression level.
+    pub fn set_compression_level(&mut self, level: CompressionLevel) {
+        self.level = level;
     }
 
-    let pos1 = w.seek(SeekFrom::Current(0))?;
+    /// Set whether change hints are checked against a full compare
+    /// of the frames.  Hints that miss a change are rejected with
+    /// `BadInput`.  This is on by default in debug builds.
+    pub fn set_verify_hints(&mut self, verify: bool) {
+        self.verify_hints = verify;
+    }
 
-    w.seek(SeekFrom::Start(pos0))?;
-    if chunk_size > 0 {
-        w.write_u32::<LE>((SIZE_OF_CHUNK + chunk_size) as u32)?;
-        w.write_u16::<LE>(chunk_magic)?;
-        w.seek(SeekFrom::Start(pos1))?;
-        Ok(SIZE_OF_CHUNK + chunk_size)
-    } else {
-        Ok(0)
+    /// Set the size and filtering of the postage stamp written with
+    /// the first frame of FLC files.
+    pub fn set_pstamp_options(&mut self, options: PstampOptions) {
+        self.pstamp = options;
     }
-}
 
-/// Write the next frame's pixels.
-fn write_pixel_data<W: Write + Seek>(
-        flic_magic: u16, prev: Option<&Raster>, next: &Raster, w: &mut W)
-        -> FlicResult<usize> {
-    let pos0 = w.seek(SeekFrom::Current(0))?;
+    /// Encode the next frame into memory.
+    ///
+    /// `frame_count` is the index of the frame in the FLIC, which
+    /// decides whether a postage stamp is included.  You must supply
+    /// the previous frame buffer, or None if it is the first frame.
+    ///
+    /// Returns the encoded frame, which is valid until the next call.
+    pub fn encode_frame(&mut self,
+            frame_count: u16, prev: Option<&Raster>, next: &Raster)
+            -> FlicResult<&[u8]> {
+        self.encode_frame_with_hint(frame_count, prev, next, None)
+    }
+
+    /// Encode the next frame into memory, only looking for changes
+    /// in the regions given by the hint.
+    ///
+    /// Returns the encoded frame, which is valid until the next call.
+    pub fn encode_frame_with_hint(&mut self,
+            frame_count: u16, prev: Option<&Raster>, next: &Raster, hint: Option<&ChangeHint>)
+            -> FlicResult<&[u8]> {
+        self.changed_spans = match (prev, hint) {
+            (Some(prev), Some(hint)) => {
+                let spans = hint.changed_spans(next.w, next.h)?;
+                if self.verify_hints {
+                    verify_changed_spans(prev, next, &spans)?;
+                }
+                Some(spans)
+            },
+            _ => None,
+        };
+
+        let res = self.encode(frame_count, prev, next);
+        self.changed_spans = None;
+        res?;
 
-    // Reserve space for chunk.
-    w.write_all(&[0; SIZE_OF_CHUNK])?;
+        Ok(&self.frame[..])
+    }
+
+    fn encode(&mut self,
+            frame_count: u16, prev: Option<&Raster>, next: &Raster)
+            -> FlicResult<()> {
+        self.frame.clear();
+        self.info = FrameEncodeInfo::default();
+
+        // Reserve space for frame header.
+        self.frame.extend_from_slice(&[0; SIZE_OF_FLIC_FRAME]);
+
+        let size_pstamp =
+            if self.flic_magic != FLIH_MAGIC && frame_count == 0 {
+                reset_buffer(&mut self.trial);
+                match write_pstamp_data_with_options(next, &self.pstamp, &mut self.trial) {
+                    Ok(size) => {
+                        self.frame.extend_from_slice(&self.trial.get_ref()[..size]);
+                        if size > 0 {
+                            self.info.pstamp = true;
+                            self.info.chunks.push(ChunkInfo { magic: FLI_PSTAMP, size: size });
+                        }
+                        size
+                    },
+                    Err(_) => 0,
+                }
+            } else {
+                0
+            };
 
-    let mut chunk_size = next.w * next.h;
-    let mut chunk_magic = FLI_COPY;
+        let size_col = self.encode_color_data(prev, next)?;
+        let size_pix = self.encode_pixel_data(prev, next)?;
+        let size = SIZE_OF_FLIC_FRAME + size_pstamp + size_col + size_pix;
 
-    // Try FLI_BLACK for first frame only.
-    if chunk_magic == FLI_COPY && prev.is_none() {
-        if can_encode_fli_black(next) {
-            chunk_size = 0;
-            chunk_magic = FLI_BLACK;
+        if size > ::std::u32::MAX as usize {
+            return Err(FlicError::ExceededLimit);
         }
+
+        let num_chunks
+            = if size_pstamp > 0 { 1 } else { 0 }
+            + if size_col > 0 { 1 } else { 0 }
+            + if size_pix > 0 { 1 } else { 0 };
+
+        assert_eq!(size, self.frame.len());
+        let mut hdr = &mut self.frame[0..8];
+        hdr.write_u32::<LE>(size as u32)?;
+        hdr.write_u16::<LE>(FCID_FRAME)?;
+        hdr.write_u16::<LE>(num_chunks)?;
+
+        self.info.size = size;
+        Ok(())
+    }
+
+    /// How the last frame was encoded.
+    pub fn last_frame_info(&self) -> &FrameEncodeInfo {
+        &self.info
+    }
+
+    /// Encode the next frame and write it out.
+    ///
+    /// Only the chosen encoding is written, so any `Write` sink can
+    /// be used.  Returns the size of the frame.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use flic::FrameEncoder;
+    /// use flic::flic::FLIHR_MAGIC;
+    ///
+    /// let buf = [0; 8 * 4];
+    /// let pal = [0; 3 * 256];
+    /// let raster = flic::Raster::new(8, 4, &buf, &pal);
+    ///
+    /// let mut out = Vec::new();
+    /// let mut encoder = FrameEncoder::new(FLIHR_MAGIC).unwrap();
+    /// let size = encoder.write_frame(0, None, &raster, &mut out).unwrap();
+    /// assert_eq!(size, out.len());
+    /// ```
+    pub fn write_frame<W: Write>(&mut self,
+            frame_count: u16, prev: Option<&Raster>, next: &Raster, w: &mut W)
+            -> FlicResult<usize> {
+        let buf = self.encode_frame(frame_count, prev, next)?;
+        w.write_all(buf)?;
+        Ok(buf.len())
     }
 
-    // Try FLI_LC.
-    if chunk_magic == FLI_COPY && prev.is_some() {
-        match encode_fli_lc(prev.unwrap(), next, w) {
-            Ok(size) =>
-                if size == 0 {
-                    w.seek(SeekFrom::Start(pos0))?;
-                    return Ok(0);
-                } else if size < chunk_size {
-                    chunk_size = size;
-                    chunk_magic = FLI_LC;
-                },
+    /// Encode the next frame's palette.
+    fn encode_color_data(&mut self, prev: Option<&Raster>, next: &Raster)
+            -> FlicResult<usize> {
+        reset_buffer(&mut self.trial);
+
+        let (chunk_size, chunk_magic) =
+            if self.flic_magic == FLIH_MAGIC {
+                let size = encode_fli_color64(prev, next, &mut self.trial)?;
+                (size, FLI_COLOR64)
+            } else {
+                let size = encode_fli_color256(prev, next, &mut self.trial)?;
+                (size, FLI_COLOR256)
+            };
 
-            Err(FlicError::ExceededLimit) => (),
-            Err(e) => return Err(e),
+        if SIZE_OF_CHUNK + chunk_size > ::std::u32::MAX as usize {
+            return Err(FlicError::ExceededLimit);
         }
 
-        if chunk_magic != FLI_LC {
-            w.seek(SeekFrom::Start(pos0 + SIZE_OF_CHUNK as u64))?;
+        if chunk_size > 0 {
+            self.frame.write_u32::<LE>((SIZE_OF_CHUNK + chunk_size) as u32)?;
+            self.frame.write_u16::<LE>(chunk_magic)?;
+            self.frame.extend_from_slice(&self.trial.get_ref()[..chunk_size]);
+            self.info.palette = true;
+            self.info.chunks.push(ChunkInfo { magic: chunk_magic, size: SIZE_OF_CHUNK + chunk_size });
+            Ok(SIZE_OF_CHUNK + chunk_size)
+        } else {
+            Ok(0)
         }
     }
 
-    // Try FLI_SS2, which has higher limits.
-    if flic_magic == FLIHR_MAGIC && chunk_magic == FLI_COPY && prev.is_some() {
-        match encode_fli_ss2(prev.unwrap(), next, w) {
-            Ok(size) =>
-                if size < chunk_size {
-                    chunk_size = size;
-                    chunk_magic = FLI_SS2;
-                },
+    /// Encode the next frame's pixels.
+    ///
+    /// Each candidate encoding is written into a scratch buffer, and
+    /// only the smallest is kept.
+    fn encode_pixel_data(&mut self, prev: Option<&Raster>, next: &Raster)
+            -> FlicResult<usize> {
+        let mut chunk_size = next.w * next.h;
+        let mut chunk_magic = FLI_COPY;
+
+        reset_buffer(&mut self.best);
+
+        // Try FLI_BLACK for first frame only.
+        if chunk_magic == FLI_COPY && prev.is_none() {
+            if can_encode_fli_black(next) {
+                chunk_size = 0;
+                chunk_magic = FLI_BLACK;
+            }
+        }
+
+        // Try FLI_LC.
+        if chunk_magic == FLI_COPY && prev.is_some() {
+            reset_buffer(&mut self.trial);
+            let optimal = self.level == CompressionLevel::Max;
+            let res = match self.changed_spans {
+                Some(ref spans) =>
+                    encode_fli_lc_hinted(prev.unwrap(), next, spans, optimal, &mut self.trial),
+                None if optimal => encode_fli_lc_optimal(prev.unwrap(), next, &mut self.trial),
+                None => encode_fli_lc(prev.unwrap(), next, &mut self.trial),
+            };
 
-            Err(FlicError::ExceededLimit) => {},
-            Err(e) => return Err(e),
+            match res {
+                Ok(size) =>
+                    if size == 0 {
+                        return Ok(0);
+                    } else if size < chunk_size {
+                        chunk_size = size;
+                        chunk_magic = FLI_LC;
+                        ::std::mem::swap(&mut self.best, &mut self.trial);
+                    } else {
+                        self.info.rejected.push(ChunkInfo { magic: FLI_LC, size: SIZE_OF_CHUNK + size });
+                    },
+
+                Err(FlicError::ExceededLimit) => (),
+                Err(e) => return Err(e),
+            }
         }
 
-        if chunk_magic != FLI_SS2 {
-            w.seek(SeekFrom::Start(pos0 + SIZE_OF_CHUNK as u64))?;
+        // Try FLI_SS2, which has higher limits.
+        if self.flic_magic == FLIHR_MAGIC && chunk_magic == FLI_COPY && prev.is_some() {
+            reset_buffer(&mut self.trial);
+            let optimal = self.level == CompressionLevel::Max;
+            let res = match self.changed_spans {
+                Some(ref spans) =>
+                    encode_fli_ss2_hinted(prev.unwrap(), next, spans, optimal, &mut self.trial),
+                None if optimal => encode_fli_ss2_optimal(prev.unwrap(), next, &mut self.trial),
+                None => encode_fli_ss2(prev.unwrap(), next, &mut self.trial),
+            };
+
+            match res {
+                Ok(size) =>
+                    if size < chunk_size {
+                        chunk_size = size;
+                        chunk_magic = FLI_SS2;
+                        ::std::mem::swap(&mut self.best, &mut self.trial);
+                    } else {
+                        self.info.rejected.push(ChunkInfo { magic: FLI_SS2, size: SIZE_OF_CHUNK + size });
+                    },
+
+                Err(FlicError::ExceededLimit) => {},
+                Err(e) => return Err(e),
+            }
         }
-    }
 
-    // Try FLI_BRUN.
-    if chunk_magic == FLI_COPY {
-        match encode_fli_brun(next, w) {
-            Ok(size) =>
-                if size < chunk_size {
-                    chunk_size = size;
-                    chunk_magic = FLI_BRUN;
-                },
+        // Try FLI_BRUN.
+        if chunk_magic == FLI_COPY {
+            reset_buffer(&mut self.trial);
+            match encode_fli_brun(next, &mut self.trial) {
+                Ok(size) =>
+                    if size < chunk_size {
+                        chunk_size = size;
+                        chunk_magic = FLI_BRUN;
+                        ::std::mem::swap(&mut self.best, &mut self.trial);
+                    } else {
+                        self.info.rejected.push(ChunkInfo { magic: FLI_BRUN, size: SIZE_OF_CHUNK + size });
+                    },
+
+                Err(FlicError::ExceededLimit) => (),
+                Err(e) => return Err(e),
+            }
+        }
 
-            Err(FlicError::ExceededLimit) => (),
-            Err(e) => return Err(e),
+        // Try FLI_COPY.
+        if chunk_magic == FLI_COPY {
+            chunk_size = encode_fli_copy(next, &mut self.best)?;
+            chunk_magic = FLI_COPY;
         }
 
-        if chunk_magic != FLI_BRUN {
-            w.seek(SeekFrom::Start(pos0 + SIZE_OF_CHUNK as u64))?;
+        if SIZE_OF_CHUNK + chunk_size > ::std::u32::MAX as usize {
+            return Err(FlicError::ExceededLimit);
         }
+
+        self.frame.write_u32::<LE>((SIZE_OF_CHUNK + chunk_size) as u32)?;
+        self.frame.write_u16::<LE>(chunk_magic)?;
+        self.frame.extend_from_slice(&self.best.get_ref()[..chunk_size]);
+        self.info.chunks.push(ChunkInfo { magic: chunk_magic, size: SIZE_OF_CHUNK + chunk_size });
+
+        Ok(SIZE_OF_CHUNK + chunk_size)
     }
+}
+
+/// Empty a scratch buffer for reuse, keeping its allocation.
+fn reset_buffer(buf: &mut Cursor<Vec<u8>>) {
+    buf.get_mut().clear();
+    buf.set_position(0);
+}
 
-    // Try FLI_COPY.
-    if chunk_magic == FLI_COPY {
-        chunk_size = encode_fli_copy(next, w)?;
-        chunk_magic = FLI_COPY;
+#[cfg(test)]
+pub mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Cursor,Seek,SeekFrom,Write};
+    use std::ops::Deref;
+    use std::path::{Path,PathBuf};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize,Ordering};
+    use byteorder::LittleEndian as LE;
+    use byteorder::{ByteOrder,ReadBytesExt,WriteBytesExt};
+    use ::{Raster,RasterMut};
+    use ::codec::{ChangeHint,FLI_BLACK,FLI_COLOR256,FLI_COPY,FLI_LC,FLI_PSTAMP};
+    use super::{FCID_FRAME,FLIH_MAGIC,FLIHR_MAGIC,SIZE_OF_CHUNK,SIZE_OF_FLIC_FRAME};
+    use ::quantize::{Dither,TruecolorFormat};
+    use ::ratectl::RateBudget;
+    use super::{FlicFile,FlicFileWriter,FlicHeaderEditor,FrameEncoder};
+
+    /// A file in the temporary directory, named uniquely for this
+    /// process, and removed when dropped.
+    pub struct TempFile(PathBuf);
+
+    impl TempFile {
+        pub fn new(name: &str) -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let n = COUNT.fetch_add(1, Ordering::Relaxed);
+            TempFile(env::temp_dir().join(
+                    format!("libflic-test-{}-{}-{}", process::id(), n, name)))
+        }
     }
 
-    let pos1 = w.seek(SeekFrom::Current(0))?;
-    assert_eq!(SIZE_OF_CHUNK + chunk_size, (pos1 - pos0) as usize);
+    impl Deref for TempFile {
+        type Target = Path;
 
-    w.seek(SeekFrom::Start(pos0))?;
-    if pos1 - pos0 > ::std::u32::MAX as u64 {
-        return Err(FlicError::ExceededLimit);
+        fn deref(&self) -> &Path {
+            &self.0
+        }
     }
 
-    w.write_u32::<LE>((pos1 - pos0) as u32)?;
-    w.write_u16::<LE>(chunk_magic)?;
-    w.seek(SeekFrom::Start(pos1))?;
+    impl AsRef<Path> for TempFile {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
 
-    Ok((pos1 - pos0) as usize)
-}
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::io::{Cursor,Seek,SeekFrom};
-    use byteorder::LittleEndian as LE;
-    use byteorder::ReadBytesExt;
-    use ::Raster;
-    use ::codec::FLI_COPY;
-    use super::{FLIH_MAGIC,SIZE_OF_CHUNK,write_pixel_data};
+    /// Write a FLC containing the frames, followed by the ring frame.
+    pub fn write_test_flic(filename: &Path, frames: &[Raster]) {
+        let mut flic = FlicFileWriter::create(
+                filename, frames[0].w as u16, frames[0].h as u16, 70)
+                .expect("create");
+
+        let mut prev = None;
+        for next in frames.iter().chain(frames.iter().take(1)) {
+            flic.write_next_frame(prev, next).expect("frame");
+            prev = Some(next);
+        }
+
+        flic.close().expect("close");
+    }
 
-    /// Test write_pixel_data output when reverting to FLI_COPY.
+    /// Test encode_pixel_data output when reverting to FLI_COPY.
     #[test]
     fn test_write_pixel_data_fli_copy() {
         const SCREEN_W: usize = 1;
@@ -1181,11 +2923,714 @@ mod tests {
         let next = Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
         let mut w = Cursor::new(Vec::new());
 
-        let res = write_pixel_data(FLIH_MAGIC, None, &next, &mut w);
+        let mut encoder = FrameEncoder::new(FLIH_MAGIC).expect("encoder");
+        let res = encoder.encode_pixel_data(None, &next);
         assert_eq!(res.expect("size"), expected_size);
+        w.write_all(&encoder.frame).expect("write");
 
         w.seek(SeekFrom::Start(0)).expect("reset");
         assert_eq!(w.read_u32::<LE>().expect("size"), expected_size as u32);
         assert_eq!(w.read_u16::<LE>().expect("magic"), FLI_COPY);
     }
+
+    /// Test encoding frames into a sink that cannot seek.
+    #[test]
+    fn test_frame_encoder() {
+        const SCREEN_W: usize = 16;
+        const SCREEN_H: usize = 4;
+        let buf0 = [0; SCREEN_W * SCREEN_H];
+        let buf1: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 3) as u8).collect();
+        let pal = [0x40; 3 * 256];
+        let raster0 = Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal);
+        let raster1 = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+
+        let mut out = Vec::new();
+        let mut encoder = FrameEncoder::new(FLIHR_MAGIC).expect("encoder");
+        let size0 = encoder.write_frame(0, None, &raster0, &mut out).expect("frame 0");
+        let size1 = encoder.write_frame(1, Some(&raster0), &raster1, &mut out).expect("frame 1");
+        assert_eq!(size0 + size1, out.len());
+        assert!(size0 >= SIZE_OF_FLIC_FRAME);
+
+        let mut r = Cursor::new(&out[size0..]);
+        assert_eq!(r.read_u32::<LE>().expect("size"), size1 as u32);
+        assert_eq!(r.read_u16::<LE>().expect("magic"), 0xF1FA);
+
+        // The frames match those written by the FLIC writer.
+        let filename = TempFile::new("frame-encoder.flc");
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            flic.write_next_frame(None, &raster0).expect("frame 0");
+            flic.write_next_frame(Some(&raster0), &raster1).expect("frame 1");
+            flic.write_next_frame(Some(&raster1), &raster0).expect("ring");
+            flic.close().expect("close");
+        }
+
+        let data = fs::read(&filename).expect("read");
+        assert_eq!(&data[128..(128 + out.len())], &out[..]);
+
+        assert!(FrameEncoder::new(0x1234).is_err());
+
+        // Change hints give the same frame, and bad hints are caught.
+        encoder.set_verify_hints(true);
+        let rows = [ true; SCREEN_H ];
+        let frame1 = encoder.encode_frame_with_hint(1, Some(&raster0), &raster1,
+                Some(&ChangeHint::Rows(&rows))).expect("frame 1").to_vec();
+        assert_eq!(&frame1[..], &out[size0..]);
+
+        let rows = [ false; SCREEN_H ];
+        assert!(encoder.encode_frame_with_hint(1, Some(&raster0), &raster1,
+                Some(&ChangeHint::Rows(&rows))).is_err());
+    }
+
+    /// Test the per-frame encoding report and summary.
+    #[test]
+    fn test_frame_encode_info() {
+        const SCREEN_W: usize = 16;
+        const SCREEN_H: usize = 4;
+        let buf0: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 7) as u8).collect();
+        let mut buf1 = buf0.clone();
+        buf1[5] = 0xFF;
+        let pal = [0x40; 3 * 256];
+        let raster0 = Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal);
+        let raster1 = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let filename = TempFile::new("frame-encode-info.flc");
+
+        let mut flic = FlicFileWriter::create(
+                &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                .expect("create");
+
+        let info0 = flic.write_next_frame(None, &raster0).expect("frame 0");
+        assert!(info0.palette);
+        assert!(info0.pstamp);
+        assert_eq!(info0.chunks.len(), 3);
+        assert_eq!(info0.chunks[0].magic, FLI_PSTAMP);
+        assert_eq!(info0.chunks[1].magic, FLI_COLOR256);
+        assert_eq!(info0.size, SIZE_OF_FLIC_FRAME
+                + info0.chunks.iter().map(|c| c.size).sum::<usize>());
+
+        let info1 = flic.write_next_frame(Some(&raster0), &raster1).expect("frame 1");
+        assert!(!info1.palette);
+        assert!(!info1.pstamp);
+        assert_eq!(info1.chunks.len(), 1);
+        assert_eq!(info1.chunks[0].magic, FLI_LC);
+        assert!(info1.rejected.is_empty());
+
+        flic.write_next_frame(Some(&raster1), &raster0).expect("ring");
+        let summary = flic.close().expect("close");
+        assert_eq!(summary.frames, 3);
+        assert_eq!(summary.palette_chunks, 1);
+        assert_eq!(summary.pstamps, 1);
+        assert_eq!(summary.chunks[&FLI_LC].count, 2);
+        assert_eq!(summary.size + 128, fs::metadata(&filename).expect("metadata").len());
+        assert!(format!("{}", summary).contains("FLI_LC: 2 chunks"));
+    }
+
+    /// Test writing postage stamps with custom options.
+    #[test]
+    fn test_pstamp_options() {
+        use ::pstamp::{PstampFilter,PstampOptions};
+        const SCREEN_W: usize = 64;
+        const SCREEN_H: usize = 32;
+        let buf: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 3) as u8).collect();
+        let pal: Vec<u8> = (0..(3 * 256)).map(|x| x as u8).collect();
+        let raster = Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
+
+        let mut encoder = FrameEncoder::new(FLIHR_MAGIC).expect("encoder");
+        encoder.set_pstamp_options(PstampOptions {
+            max_w: 32,
+            max_h: 32,
+            filter: PstampFilter::Average,
+            dither: true,
+        });
+
+        let frame = encoder.encode_frame(0, None, &raster).expect("frame").to_vec();
+        assert!(encoder.last_frame_info().pstamp);
+        let mut r = Cursor::new(&frame[SIZE_OF_FLIC_FRAME..]);
+        let _size = r.read_u32::<LE>().expect("size");
+        assert_eq!(r.read_u16::<LE>().expect("magic"), FLI_PSTAMP);
+        assert_eq!(r.read_u16::<LE>().expect("height"), 16);
+        assert_eq!(r.read_u16::<LE>().expect("width"), 32);
+
+        encoder.set_pstamp_options(PstampOptions { max_w: 0, ..PstampOptions::default() });
+        encoder.encode_frame(0, None, &raster).expect("frame");
+        assert!(!encoder.last_frame_info().pstamp);
+    }
+
+    /// Test reading postage stamps into owned thumbnails.
+    #[test]
+    fn test_read_thumbnail() {
+        use ::pstamp::PstampOptions;
+        const SCREEN_W: usize = 64;
+        const SCREEN_H: usize = 32;
+        let buf: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 5) as u8).collect();
+        let pal = [0xFF; 3 * 256];
+        let raster = Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
+        let filename = TempFile::new("read-thumbnail.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            flic.set_pstamp_options(PstampOptions { max_w: 32, max_h: 32, ..PstampOptions::default() });
+            flic.write_next_frame(None, &raster).expect("frame 0");
+            flic.write_next_frame(Some(&raster), &raster).expect("ring");
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.pstamp_native_size().expect("size"), Some((32, 16)));
+
+        let thumbnail = flic.read_thumbnail().expect("thumbnail");
+        assert_eq!((thumbnail.w, thumbnail.h), (32, 16));
+        assert_eq!(thumbnail.native_size, Some((32, 16)));
+        assert!(thumbnail.buf.iter().all(|&c| c == 215));
+        assert_eq!(&thumbnail.pal[(3 * 215)..(3 * 216)], &[213, 213, 213]);
+
+        // The destination raster is only borrowed for the call.
+        let mut buf = [0; 8 * 4];
+        let mut pal = [0; 3 * 256];
+        for _ in 0..2 {
+            let mut dst = RasterMut::new(8, 4, &mut buf, &mut pal);
+            flic.read_postage_stamp(&mut dst).expect("pstamp");
+        }
+        assert!(buf.iter().all(|&c| c == 215));
+    }
+
+    /// Test creating thumbnails from frames without usable stamps.
+    #[test]
+    fn test_read_thumbnail_fallback() {
+        const SCREEN_W: usize = 320;
+        const SCREEN_H: usize = 200;
+        let black = [0; SCREEN_W * SCREEN_H];
+        let mut image = [0; SCREEN_W * SCREEN_H];
+        for row in image.chunks_mut(SCREEN_W) {
+            for e in row[0..(SCREEN_W / 2)].iter_mut() {
+                *e = 1;
+            }
+        }
+        let pal_black = [0; 3 * 256];
+        let mut pal = [0; 3 * 256];
+        pal[3..6].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+        let raster_black = Raster::new(SCREEN_W, SCREEN_H, &black, &pal_black);
+        let raster_image = Raster::new(SCREEN_W, SCREEN_H, &image, &pal);
+        let filename = TempFile::new("read-thumbnail-fallback.fli");
+
+        // First frame is FLI_BLACK followed by a delta.
+        let mut encoder = FrameEncoder::new(FLIH_MAGIC).expect("encoder");
+        let delta = encoder.encode_frame(0, Some(&raster_black), &raster_image)
+                .expect("frame 0").to_vec();
+        let mut frame0 = Vec::new();
+        frame0.write_u32::<LE>((delta.len() + SIZE_OF_CHUNK) as u32).expect("size");
+        frame0.write_u16::<LE>(FCID_FRAME).expect("magic");
+        frame0.write_u16::<LE>(LE::read_u16(&delta[6..8]) + 1).expect("chunks");
+        frame0.extend_from_slice(&delta[8..SIZE_OF_FLIC_FRAME]);
+        frame0.write_u32::<LE>(SIZE_OF_CHUNK as u32).expect("size");
+        frame0.write_u16::<LE>(FLI_BLACK).expect("magic");
+        frame0.extend_from_slice(&delta[SIZE_OF_FLIC_FRAME..]);
+        let ring = encoder.encode_frame(1, Some(&raster_image), &raster_image)
+                .expect("ring").to_vec();
+
+        {
+            let mut flic = FlicFileWriter::create_fli(&filename, 5).expect("create");
+            flic.write_raw_frame(&frame0).expect("frame 0");
+            flic.write_raw_frame(&ring).expect("ring");
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.pstamp_native_size().expect("size"), None);
+
+        let thumbnail = flic.read_thumbnail().expect("thumbnail");
+        assert_eq!((thumbnail.w, thumbnail.h), (100, 63));
+        assert_eq!(thumbnail.native_size, None);
+        assert_eq!(thumbnail.buf[0], 215);
+        assert_eq!(thumbnail.buf[99], 0);
+
+        let thumbnail = flic.read_thumbnail_of_frame(0).expect("thumbnail");
+        assert_eq!(thumbnail.buf[0], 215);
+        assert!(flic.read_thumbnail_of_frame(1).is_err());
+        assert_eq!(flic.frame(), 0);
+    }
+
+    /// Test decoding frames straight into RGBA.
+    #[test]
+    fn test_read_next_frame_rendered() {
+        use ::render::RenderFormat;
+        const SCREEN_W: usize = 4;
+        const SCREEN_H: usize = 2;
+        let buf0 = [ 0, 1, 1, 0, 1, 0, 0, 1 ];
+        let buf1 = [ 1, 1, 1, 0, 1, 0, 0, 1 ];
+        let mut pal = [0; 3 * 256];
+        pal[3..6].copy_from_slice(&[0x10, 0x20, 0x30]);
+        let raster0 = Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal);
+        let raster1 = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let filename = TempFile::new("read-next-frame-rendered.flc");
+        write_test_flic(&filename, &[raster0, raster1]);
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        let mut rgba = [0; 4 * SCREEN_W * SCREEN_H];
+        flic.read_next_frame_rendered(RenderFormat::Rgba32, Some(0), &mut rgba, 4 * SCREEN_W)
+                .expect("frame 0");
+        assert_eq!(&rgba[0..8], &[0, 0, 0, 0, 0x10, 0x20, 0x30, 0xFF]);
+
+        // The delta frame is applied to the kept indexed screen.
+        flic.read_next_frame_rendered(RenderFormat::Rgba32, Some(0), &mut rgba, 4 * SCREEN_W)
+                .expect("frame 1");
+        assert_eq!(&rgba[0..8], &[0x10, 0x20, 0x30, 0xFF, 0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(flic.screen().expect("screen").buf(), &buf1[..]);
+    }
+
+    /// Test reporting the regions modified by each frame.
+    #[test]
+    fn test_read_next_frame_changes() {
+        use ::Rect;
+        const SCREEN_W: usize = 16;
+        const SCREEN_H: usize = 8;
+        let buf0: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 7) as u8).collect();
+        let mut buf1 = buf0.clone();
+        buf1[SCREEN_W * 2 + 3] = 0xFF;
+        buf1[SCREEN_W * 4 + 5] = 0xFF;
+        let pal: Vec<u8> = (0..(3 * 256)).map(|x| x as u8).collect();
+        let raster0 = Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal);
+        let raster1 = Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal);
+        let filename = TempFile::new("read-next-frame-changes.flc");
+        write_test_flic(&filename, &[raster0, raster1]);
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        let mut frame = flic.new_frame();
+
+        let res = flic.read_next_frame_into(&mut frame).expect("frame 0");
+        assert_eq!(res.changes.rect, Some(Rect { x: 0, y: 0, w: SCREEN_W, h: SCREEN_H }));
+        assert_eq!(res.changes.palette, Some(0..256));
+
+        let res = flic.read_next_frame_into(&mut frame).expect("frame 1");
+        assert_eq!(res.changes.rect, Some(Rect { x: 3, y: 2, w: 3, h: 3 }));
+        assert_eq!(res.changes.palette, None);
+    }
+
+    /// Test holding frames with empty frames.
+    #[test]
+    fn test_write_next_frame_with_duration() {
+        const SCREEN_W: usize = 8;
+        const SCREEN_H: usize = 4;
+        let bufs: Vec<Vec<u8>> = (0..3)
+            .map(|i| vec![i as u8; SCREEN_W * SCREEN_H])
+            .collect();
+        let pal = [0x80; 3 * 256];
+        let rasters: Vec<Raster> = bufs.iter()
+            .map(|buf| Raster::new(SCREEN_W, SCREEN_H, buf, &pal))
+            .collect();
+        let filename = TempFile::new("duration.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 50)
+                    .expect("create");
+            assert!(flic.timing_report().is_none());
+
+            flic.write_next_frame_with_duration(None, &rasters[0], 200).expect("frame 0");
+            flic.write_next_frame_with_duration(Some(&rasters[0]), &rasters[1], 40).expect("frame 1");
+            flic.write_next_frame_with_duration(Some(&rasters[1]), &rasters[2], 110).expect("frame 2");
+            flic.write_next_frame(Some(&rasters[2]), &rasters[0]).expect("ring");
+
+            let report = flic.timing_report().expect("report");
+            assert_eq!(report.frames, 3);
+            assert_eq!(report.ticks, 7);
+            assert_eq!(report.target_msec, 350.0);
+            assert_eq!(report.total_error_msec, 0.0);
+            assert_eq!(report.max_error_msec, 10.0);
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.frame_count(), 7);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for &i in [ 0, 0, 0, 0, 1, 2, 2, 0, 0 ].iter() {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+            assert_eq!(&buf[..], &bufs[i][..]);
+        }
+    }
+
+    /// Test recording held frames with the rate controller.
+    #[test]
+    fn test_write_next_frame_with_duration_rate_control() {
+        const SCREEN_W: usize = 8;
+        const SCREEN_H: usize = 4;
+        let bufs: Vec<Vec<u8>> = (0..3)
+            .map(|i| vec![i as u8; SCREEN_W * SCREEN_H])
+            .collect();
+        let pal = [0x80; 3 * 256];
+        let rasters: Vec<Raster> = bufs.iter()
+            .map(|buf| Raster::new(SCREEN_W, SCREEN_H, buf, &pal))
+            .collect();
+        let filename = TempFile::new("duration-rate-control.flc");
+
+        let mut flic = FlicFileWriter::create(
+                &filename, SCREEN_W as u16, SCREEN_H as u16, 50)
+                .expect("create");
+        flic.set_rate_control(RateBudget::FileSize(4096, 7), 0).expect("rate control");
+
+        flic.write_next_frame_with_duration(None, &rasters[0], 200).expect("frame 0");
+        flic.write_next_frame_with_duration(Some(&rasters[0]), &rasters[1], 40).expect("frame 1");
+        flic.write_next_frame_with_duration(Some(&rasters[1]), &rasters[2], 110).expect("frame 2");
+
+        let report = flic.rate_control_report().expect("report").expect("rate control");
+        assert_eq!(report.frames, 7);
+        assert_eq!(report.dropped_frames, 0);
+        assert_eq!(report.mean_squared_error, 0.0);
+
+        flic.write_next_frame(Some(&rasters[2]), &rasters[0]).expect("ring");
+        flic.close().expect("close");
+    }
+
+    /// Test appending frames to an existing FLC.
+    #[test]
+    fn test_append() {
+        const SCREEN_W: usize = 8;
+        const SCREEN_H: usize = 4;
+        let pal = [0x80; 3 * 256];
+        let frames: Vec<Vec<u8>> = (0..4)
+            .map(|i| (0..(SCREEN_W * SCREEN_H)).map(|x| (x * i) as u8).collect())
+            .collect();
+
+        let filename = TempFile::new("append.flc");
+        write_test_flic(&filename, &[
+                Raster::new(SCREEN_W, SCREEN_H, &frames[0], &pal),
+                Raster::new(SCREEN_W, SCREEN_H, &frames[1], &pal) ]);
+
+        {
+            let mut flic = FlicFileWriter::append(&filename).expect("append");
+            for frame in &frames[2..] {
+                let raster = Raster::new(SCREEN_W, SCREEN_H, frame, &pal);
+                flic.write_next_frame(None, &raster).expect("frame");
+            }
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.frame_count(), 4);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for i in 0..5 {
+            let res = flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+            assert_eq!(&buf[..], &frames[i % 4][..]);
+            assert_eq!(res.looped, i == 4);
+        }
+    }
+
+    /// Test merging near-identical pixels into the previous frame.
+    #[test]
+    fn test_pixel_tolerance() {
+        const SCREEN_W: usize = 16;
+        const SCREEN_H: usize = 4;
+        const TOLERANCE: u16 = 4;
+        let mut pal = [0; 3 * 256];
+        for c in 0..256 {
+            pal[3 * c + 0] = c as u8;
+            pal[3 * c + 1] = c as u8;
+            pal[3 * c + 2] = c as u8;
+        }
+
+        // Noisy frames: a ramp with a small amount of jitter.
+        let frames: Vec<Vec<u8>> = (0..4)
+            .map(|i| (0..(SCREEN_W * SCREEN_H))
+                .map(|x| (4 * (x % SCREEN_W) + (x * 7 + i) % 3) as u8)
+                .collect())
+            .collect();
+
+        let filename = TempFile::new("pixel-tolerance.flc");
+        let filename_lossless = TempFile::new("pixel-tolerance-0.flc");
+
+        for &(filename, tolerance) in &[(&filename, TOLERANCE), (&filename_lossless, 0)] {
+            let mut flic = FlicFileWriter::create(
+                    filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            flic.set_pixel_tolerance(tolerance).expect("tolerance");
+            let mut prev: Option<&Vec<u8>> = None;
+            for frame in frames.iter().chain(frames.iter().take(1)) {
+                let raster = Raster::new(SCREEN_W, SCREEN_H, frame, &pal);
+                match prev {
+                    Some(prev) => {
+                        let prev = Raster::new(SCREEN_W, SCREEN_H, prev, &pal);
+                        flic.write_next_frame(Some(&prev), &raster).expect("frame");
+                    },
+                    None => { flic.write_next_frame(None, &raster).expect("frame"); },
+                }
+                prev = Some(frame);
+            }
+            flic.close().expect("close");
+        }
+
+        let size = fs::metadata(&filename).expect("metadata").len();
+        let size_lossless = fs::metadata(&filename_lossless).expect("metadata").len();
+        assert!(size < size_lossless);
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.frame_count(), 4);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for i in 0..5 {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+
+            let frame = &frames[i % 4];
+            if i % 4 == 0 {
+                assert_eq!(&buf[..], &frame[..]);
+            } else {
+                // Within twice the tolerance on each channel.
+                for (&a, &b) in buf.iter().zip(frame.iter()) {
+                    assert!((a as i32 - b as i32).abs() <= 2 * TOLERANCE as i32);
+                }
+            }
+        }
+    }
+
+    /// Test keeping encoded frames within a byte budget.
+    #[test]
+    fn test_rate_control() {
+        const SCREEN_W: usize = 32;
+        const SCREEN_H: usize = 16;
+        const NUM_FRAMES: usize = 8;
+        const BUDGET: u64 = 2048;
+        let mut pal = [0; 3 * 256];
+        for c in 0..256 {
+            pal[3 * c + 0] = c as u8;
+            pal[3 * c + 1] = c as u8;
+            pal[3 * c + 2] = c as u8;
+        }
+
+        let frames: Vec<Vec<u8>> = (0..NUM_FRAMES)
+            .map(|i| (0..(SCREEN_W * SCREEN_H))
+                .map(|x| (4 * (x % SCREEN_W) + (x * 7 + i * 5) % 11) as u8)
+                .collect())
+            .collect();
+
+        let filename = TempFile::new("rate-control.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            flic.set_rate_control(RateBudget::FileSize(BUDGET, NUM_FRAMES as u16), 4)
+                    .expect("rate control");
+            for frame in frames.iter().chain(frames.iter().take(1)) {
+                let raster = Raster::new(SCREEN_W, SCREEN_H, frame, &pal);
+                flic.write_next_frame(None, &raster).expect("frame");
+            }
+
+            let report = flic.rate_control_report().expect("report").expect("rate control");
+            assert_eq!(report.frames as usize, NUM_FRAMES + 1);
+            assert!(report.size <= BUDGET);
+            assert!(report.max_tolerance > 0);
+            assert!(report.mean_squared_error > 0.0);
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.frame_count() as usize, NUM_FRAMES);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for i in 0..(NUM_FRAMES + 1) {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+            if i % NUM_FRAMES == 0 {
+                assert_eq!(&buf[..], &frames[0][..]);
+            }
+        }
+    }
+
+    /// Test writing frames from truecolor images.
+    #[test]
+    fn test_write_next_frame_rgb() {
+        const SCREEN_W: usize = 24;
+        const SCREEN_H: usize = 8;
+        let frames: Vec<Vec<u8>> = (0..3)
+            .map(|i| (0..(SCREEN_W * SCREEN_H))
+                .flat_map(|x| vec![ (10 * (x % SCREEN_W)) as u8, (30 * i) as u8, (x / SCREEN_W) as u8 ])
+                .collect())
+            .collect();
+
+        let filename = TempFile::new("write-rgb.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            for frame in frames.iter().chain(frames.iter().take(1)) {
+                flic.write_next_frame_rgb(frame, TruecolorFormat::Rgb24, Dither::None)
+                        .expect("frame");
+            }
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.frame_count(), 3);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for i in 0..4 {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+
+            // Fewer than 256 colours, so the frames are exact.
+            let rgb: Vec<u8> = buf.iter()
+                .flat_map(|&c| pal[(3 * c as usize)..(3 * c as usize + 3)].to_vec())
+                .collect();
+            assert_eq!(&rgb[..], &frames[i % 3][..]);
+        }
+    }
+
+    /// Test editing the FLC header in place.
+    #[test]
+    fn test_header_editor() {
+        const SCREEN_W: usize = 8;
+        const SCREEN_H: usize = 4;
+        let buf = [0x12; SCREEN_W * SCREEN_H];
+        let pal = [0x80; 3 * 256];
+        let filename = TempFile::new("header-editor.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            let raster = Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
+            flic.write_next_frame(None, &raster).expect("frame 0");
+            flic.write_next_frame(Some(&raster), &raster).expect("ring");
+            flic.close().expect("close");
+        }
+
+        let size = fs::metadata(&filename).expect("metadata").len();
+
+        {
+            let mut editor = FlicHeaderEditor::open(&filename).expect("open");
+            editor.set_speed_jiffies(7);
+            editor.set_creator(0x464C4942, 1234);
+            editor.set_aspect_ratio(6, 5);
+            editor.close().expect("close");
+        }
+
+        assert_eq!(fs::metadata(&filename).expect("metadata").len(), size);
+
+        let flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.speed_msec(), 100);
+        assert_eq!(flic.speed_jiffies(), 7);
+        assert_eq!(flic.creator(), 0x464C4942);
+        assert_eq!(flic.creation_time(), 1234);
+        assert_eq!(flic.aspect_x(), 6);
+        assert_eq!(flic.aspect_y(), 5);
+        assert_eq!(flic.frame_count(), 1);
+    }
+
+    /// Test encoding frames in parallel.
+    #[test]
+    fn test_write_frames() {
+        const SCREEN_W: usize = 32;
+        const SCREEN_H: usize = 8;
+        let bufs: Vec<Vec<u8>> = (0..6)
+            .map(|i| (0..(SCREEN_W * SCREEN_H))
+                .map(|x| if (x + 3 * i) % 11 < 4 { i as u8 } else { (x % 5) as u8 })
+                .collect())
+            .collect();
+        let pal: Vec<u8> = (0..(3 * 256)).map(|c| c as u8).collect();
+        let frames: Vec<Raster> = bufs.iter()
+            .chain(bufs.iter().take(1))
+            .map(|buf| Raster::new(SCREEN_W, SCREEN_H, buf, &pal))
+            .collect();
+
+        let filename1 = TempFile::new("write-frames1.flc");
+        let filename2 = TempFile::new("write-frames2.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename1, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            let mut prev = None;
+            for next in frames.iter() {
+                flic.write_next_frame(prev, next).expect("frame");
+                prev = Some(next);
+            }
+            flic.close().expect("close");
+        }
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename2, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            flic.write_frames(None, &frames[..3], 1).expect("frames");
+            flic.write_frames(Some(&frames[2]), &frames[3..], 3).expect("frames");
+            flic.close().expect("close");
+        }
+
+        let data1 = fs::read(&filename1).expect("read");
+        let data2 = fs::read(&filename2).expect("read");
+        assert_eq!(data1, data2);
+
+        let mut flic = FlicFile::open(&filename2).expect("open");
+        assert_eq!(flic.frame_count(), 6);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for i in 0..7 {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+            assert_eq!(&buf[..], &bufs[i % 6][..]);
+        }
+    }
+
+    /// Test writing tracked frames after writing frames in parallel.
+    #[test]
+    fn test_write_frames_then_rgb() {
+        const SCREEN_W: usize = 16;
+        const SCREEN_H: usize = 4;
+        let bufs: Vec<Vec<u8>> = (0..3)
+            .map(|i| (0..(SCREEN_W * SCREEN_H)).map(|x| ((x + i) % 7) as u8).collect())
+            .collect();
+        let pal: Vec<u8> = (0..(3 * 256)).map(|c| (c / 3) as u8).collect();
+        let frames: Vec<Raster> = bufs.iter()
+            .map(|buf| Raster::new(SCREEN_W, SCREEN_H, buf, &pal))
+            .collect();
+        let rgb: Vec<u8> = bufs[0].iter().flat_map(|&c| vec![c, c, c]).collect();
+
+        let filename = TempFile::new("write-frames-rgb.flc");
+
+        {
+            let mut flic = FlicFileWriter::create(
+                    &filename, SCREEN_W as u16, SCREEN_H as u16, 70)
+                    .expect("create");
+            flic.set_pixel_tolerance(0).expect("tolerance");
+            flic.write_frames(None, &frames, 2).expect("frames");
+            flic.write_next_frame_rgb(&rgb, TruecolorFormat::Rgb24, Dither::None)
+                    .expect("frame");
+            flic.set_pixel_tolerance(0).expect("tolerance");
+            flic.close().expect("close");
+        }
+
+        let mut flic = FlicFile::open(&filename).expect("open");
+        assert_eq!(flic.frame_count(), 3);
+
+        let mut buf = [0; SCREEN_W * SCREEN_H];
+        let mut pal = [0; 3 * 256];
+        for i in 0..4 {
+            flic.read_next_frame(
+                    &mut RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal))
+                    .expect("read");
+            let grey: Vec<u8> = buf.iter().map(|&c| pal[3 * c as usize]).collect();
+            assert_eq!(&grey[..], &bufs[i % 3][..]);
+        }
+    }
 }
@@ -11,6 +11,8 @@ pub use errcode::FlicError;
 pub use errcode::FlicResult;
 pub use flic::FlicFile;
 pub use flic::FlicFileWriter;
+pub use flic::FlicHeaderEditor;
+pub use flic::FrameEncoder;
 
 /// Raster structure.
 pub struct Raster<'a> {
@@ -23,6 +25,24 @@ pub struct Raster<'a> {
     pal: &'a [u8],
 }
 
+/// Owned frame, with a screen buffer and palette.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Frame {
+    w: usize,
+    h: usize,
+    buf: Vec<u8>,
+    pal: Vec<u8>,
+}
+
+/// Rectangle, in pixels.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub w: usize,
+    pub h: usize,
+}
+
 /// Mutable raster structure.
 pub struct RasterMut<'a> {
     x: usize,
@@ -35,9 +55,16 @@ pub struct RasterMut<'a> {
 }
 
 pub mod codec;
+pub mod convert;
+pub mod edit;
 pub mod ffi;
 pub mod flic;
+pub mod palette;
 pub mod pstamp;
+pub mod quantize;
+pub mod ratectl;
+pub mod render;
+pub mod timing;
 
 mod errcode;
 mod raster;
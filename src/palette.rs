@@ -0,0 +1,509 @@
+//! Palette tools.
+//!
+//! Colours are matched by Euclidean distance in RGB.  Translation
+//! tables ("xlat" tables) map each of the 256 colour indices of one
+//! palette to an index of another palette.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use ::{FlicError,FlicResult,FlicFile,FlicFileWriter,Raster,RasterMut};
+use ::codec::apply_pstamp_xlat256;
+use ::convert::pal_8bit_to_fli_levels;
+use ::edit::write_replacing;
+use ::flic::FLIH_MAGIC;
+
+/// Nearest palette entry lookup, with a lazily filled table of
+/// results for 15-bit colours.
+///
+/// Colours exactly in the palette always map to their first entry.
+/// Other colours are cached at 15-bit precision, so the result may
+/// differ slightly from `nearest_color` for nearby colours.
+///
+/// # Examples
+///
+/// ```
+/// use flic::palette::NearestCache;
+///
+/// let pal = [ 0x00, 0x00, 0x00,  0xFF, 0xFF, 0xFF ];
+/// let mut cache = NearestCache::new(&pal);
+/// assert_eq!(cache.nearest(0xF0, 0xE0, 0xFF), 1);
+/// ```
+pub struct NearestCache {
+    pal: Vec<u8>,
+    exact: HashMap<[u8; 3], u8>,
+    cache: Vec<u16>,
+}
+
+impl NearestCache {
+    /// Create a lookup cache for the palette, up to 256 entries.
+    pub fn new(pal: &[u8])
+            -> Self {
+        let num_cols = ::std::cmp::min(pal.len() / 3, 256);
+        let pal = pal[0..(3 * num_cols)].to_vec();
+
+        let mut exact = HashMap::new();
+        for (c, e) in pal.chunks(3).enumerate() {
+            exact.entry([e[0], e[1], e[2]]).or_insert(c as u8);
+        }
+
+        NearestCache {
+            pal: pal,
+            exact: exact,
+            cache: vec![::std::u16::MAX; 32768],
+        }
+    }
+
+    /// The palette being searched.
+    pub fn pal(&self) -> &[u8] {
+        &self.pal
+    }
+
+    /// Find the palette entry nearest to the colour.
+    pub fn nearest(&mut self, r: u8, g: u8, b: u8)
+            -> u8 {
+        if let Some(&c) = self.exact.get(&[r, g, b]) {
+            return c;
+        }
+
+        let idx = ((r as usize >> 3) << 10) | ((g as usize >> 3) << 5) | (b as usize >> 3);
+        let cached = self.cache[idx];
+        if cached != ::std::u16::MAX {
+            return cached as u8;
+        }
+
+        let c = nearest_color(&self.pal, r, g, b);
+        self.cache[idx] = c as u16;
+        c
+    }
+}
+
+/// Find the palette entry nearest to the colour.
+pub fn nearest_color(pal: &[u8], r: u8, g: u8, b: u8)
+        -> u8 {
+    let mut best = 0;
+    let mut best_dist = ::std::u32::MAX;
+    for (c, e) in pal.chunks(3).enumerate().take(256) {
+        let dr = e[0] as i32 - r as i32;
+        let dg = e[1] as i32 - g as i32;
+        let db = e[2] as i32 - b as i32;
+        let dist = (dr * dr + dg * dg + db * db) as u32;
+        if dist < best_dist {
+            best = c;
+            best_dist = dist;
+            if dist == 0 {
+                break;
+            }
+        }
+    }
+
+    best as u8
+}
+
+/// Build a translation table mapping each entry of `src_pal` to the
+/// nearest entry of `dst_pal`.
+pub fn make_xlat(src_pal: &[u8], dst_pal: &[u8])
+        -> [u8; 256] {
+    let mut xlat = [0; 256];
+    for (x, e) in xlat.iter_mut().zip(src_pal.chunks(3)) {
+        *x = nearest_color(dst_pal, e[0], e[1], e[2]);
+    }
+    xlat
+}
+
+/// Map the raster's pixels through the translation table.  The
+/// palette is not changed.
+pub fn apply_xlat(xlat: &[u8; 256], dst: &mut RasterMut) {
+    apply_pstamp_xlat256(xlat, dst);
+}
+
+/// Remap the raster's pixels to the nearest colours in the new
+/// palette, and replace the raster's palette with it.
+///
+/// Returns `BadInput` if the palette does not have 256 entries.
+///
+/// # Examples
+///
+/// ```
+/// let mut buf = [0; 320 * 200];
+/// let mut pal = [0; 3 * 256];
+/// let new_pal = [0; 3 * 256];
+/// let mut raster = flic::RasterMut::new(320, 200, &mut buf, &mut pal);
+///
+/// flic::palette::remap_raster(&mut raster, &new_pal).expect("remap");
+/// ```
+pub fn remap_raster(dst: &mut RasterMut, pal: &[u8])
+        -> FlicResult<()> {
+    if pal.len() != 3 * 256 {
+        return Err(FlicError::BadInput);
+    }
+
+    let xlat = make_xlat(&dst.pal, pal);
+    apply_xlat(&xlat, dst);
+    dst.pal.copy_from_slice(pal);
+    Ok(())
+}
+
+/// Remap every frame of a FLIC to the given 256-colour palette.
+///
+/// Each frame's pixels are mapped to the nearest colours in the new
+/// palette.  FLI output keeps 6 bits per channel, so the palette is
+/// rounded to 6-bit levels first.
+///
+/// The output replaces `dst` only once it has been written in full,
+/// so `dst` may be the same file as `src`.
+pub fn remap_flic(src: &Path, dst: &Path, pal: &[u8])
+        -> FlicResult<()> {
+    remap_flic_by(src, dst, pal, |src_pal| make_xlat(src_pal, pal))
+}
+
+/// Remap every frame of a FLIC through the translation table, and
+/// replace its palette with the given 256-colour palette.
+///
+/// FLI output keeps 6 bits per channel, so the palette is rounded
+/// to 6-bit levels first.
+///
+/// The output replaces `dst` only once it has been written in full,
+/// so `dst` may be the same file as `src`.
+pub fn remap_flic_with_xlat(src: &Path, dst: &Path, xlat: &[u8; 256], pal: &[u8])
+        -> FlicResult<()> {
+    remap_flic_by(src, dst, pal, |_| *xlat)
+}
+
+/// Remap every frame of a FLIC through the translation table made
+/// for the frame's palette.
+fn remap_flic_by<F>(src: &Path, dst: &Path, pal: &[u8], make: F)
+        -> FlicResult<()>
+        where F: Fn(&[u8]) -> [u8; 256] {
+    if pal.len() != 3 * 256 {
+        return Err(FlicError::BadInput);
+    }
+
+    write_replacing(dst, |tmp| remap_flic_to(src, tmp, pal, make))
+}
+
+fn remap_flic_to<F>(src: &Path, dst: &Path, pal: &[u8], make: F)
+        -> FlicResult<()>
+        where F: Fn(&[u8]) -> [u8; 256] {
+    let mut fin = FlicFile::open(src)?;
+    let mut out_pal = pal.to_vec();
+    let mut fout =
+        if fin.magic() == FLIH_MAGIC {
+            pal_8bit_to_fli_levels(pal, &mut out_pal);
+            FlicFileWriter::create_fli(dst, fin.speed_jiffies())?
+        } else {
+            FlicFileWriter::create(dst, fin.width(), fin.height(), fin.speed_msec())?
+        };
+
+    fout.set_creator(fin.creator(), fin.creation_time());
+    fout.set_aspect_ratio(fin.aspect_x(), fin.aspect_y());
+
+    let res = remap_frames(&mut fin, &mut fout, &out_pal, make);
+
+    match res {
+        Ok(_) => fout.close().map(|_| ()),
+        Err(e) => {
+            let _ = fout.close();
+            Err(e)
+        },
+    }
+}
+
+fn remap_frames<F>(fin: &mut FlicFile, fout: &mut FlicFileWriter, pal: &[u8], make: F)
+        -> FlicResult<()>
+        where F: Fn(&[u8]) -> [u8; 256] {
+    let mut src = fin.new_frame();
+    let mut prev = fout.new_frame();
+    let mut next = fout.new_frame();
+    let mut xlat = [0; 256];
+    let mut xlat_pal = Vec::new();
+    let mut first = true;
+
+    next.pal_mut().copy_from_slice(pal);
+
+    loop {
+        let res = fin.read_next_frame_into(&mut src)?;
+
+        if src.pal() != &xlat_pal[..] {
+            xlat = make(src.pal());
+            xlat_pal = src.pal().to_vec();
+        }
+
+        for (d, &s) in next.buf_mut().iter_mut().zip(src.buf()) {
+            *d = xlat[s as usize];
+        }
+
+        if first {
+            fout.write_next_frame_from(None, &next)?;
+        } else {
+            fout.write_next_frame_from(Some(&prev), &next)?;
+        }
+
+        if res.looped {
+            break;
+        }
+
+        prev.copy_from_raster(&next.as_raster());
+        first = false;
+    }
+
+    Ok(())
+}
+
+/// Mark the colour indices used by the raster's pixels.
+pub fn mark_used(src: &Raster, used: &mut [bool; 256]) {
+    let start = src.stride * src.y;
+    let end = src.stride * (src.y + src.h);
+    for row in src.buf[start..end].chunks(src.stride) {
+        for &c in &row[src.x..(src.x + src.w)] {
+            used[c as usize] = true;
+        }
+    }
+}
+
+/// Find the colour indices used by any frame of a FLIC.
+///
+/// Entries that are never used may be given new colours, e.g. by
+/// `merge_palettes`, without changing the animation.
+pub fn used_colors_in_flic(src: &Path)
+        -> FlicResult<[bool; 256]> {
+    let mut fin = FlicFile::open(src)?;
+    let mut frame = fin.new_frame();
+    let mut used = [false; 256];
+
+    loop {
+        let res = fin.read_next_frame_into(&mut frame)?;
+        mark_used(&frame.as_raster(), &mut used);
+
+        if res.looped {
+            break;
+        }
+    }
+
+    Ok(used)
+}
+
+/// Merge the used entries of two palettes into one.
+///
+/// The used entries of palette `a` keep their indices, so pixels
+/// using `a` need no remapping.  The used entries of palette `b` are
+/// matched exactly where possible, or else placed in the entries
+/// that `a` does not use.  Returns the merged palette and the
+/// translation table for pixels using `b`.
+///
+/// Returns `ExceededLimit` if the colours do not fit in 256 entries.
+pub fn merge_palettes(
+        a_pal: &[u8], a_used: &[bool; 256],
+        b_pal: &[u8], b_used: &[bool; 256])
+        -> FlicResult<(Vec<u8>, [u8; 256])> {
+    if a_pal.len() != 3 * 256 || b_pal.len() != 3 * 256 {
+        return Err(FlicError::BadInput);
+    }
+
+    let mut pal = a_pal.to_vec();
+    let mut taken = *a_used;
+    let mut exact = HashMap::new();
+    for c in (0..256).filter(|&c| a_used[c]) {
+        exact.entry([a_pal[3 * c + 0], a_pal[3 * c + 1], a_pal[3 * c + 2]])
+            .or_insert(c as u8);
+    }
+
+    let mut xlat = [0; 256];
+    let mut free = 0;
+    for c in (0..256).filter(|&c| b_used[c]) {
+        let rgb = [b_pal[3 * c + 0], b_pal[3 * c + 1], b_pal[3 * c + 2]];
+        if let Some(&idx) = exact.get(&rgb) {
+            xlat[c] = idx;
+            continue;
+        }
+
+        while free < 256 && taken[free] {
+            free = free + 1;
+        }
+        if free >= 256 {
+            return Err(FlicError::ExceededLimit);
+        }
+
+        pal[(3 * free)..(3 * free + 3)].copy_from_slice(&rgb);
+        taken[free] = true;
+        exact.insert(rgb, free as u8);
+        xlat[c] = free as u8;
+    }
+
+    Ok((pal, xlat))
+}
+
+/// Sort a palette by luminance, darkest first.  Entries of equal
+/// luminance keep their order.  Returns the sorted palette and the
+/// translation table from old to new indices.
+///
+/// Returns `BadInput` if the palette does not have 256 entries.
+pub fn sort_by_luminance(pal: &[u8])
+        -> FlicResult<(Vec<u8>, [u8; 256])> {
+    if pal.len() != 3 * 256 {
+        return Err(FlicError::BadInput);
+    }
+
+    let mut order: Vec<usize> = (0..256).collect();
+    order.sort_by_key(|&c|
+            299 * pal[3 * c + 0] as u32 + 587 * pal[3 * c + 1] as u32 + 114 * pal[3 * c + 2] as u32);
+
+    let mut sorted = vec![0; 3 * 256];
+    let mut xlat = [0; 256];
+    for (new, &old) in order.iter().enumerate() {
+        sorted[(3 * new)..(3 * new + 3)].copy_from_slice(&pal[(3 * old)..(3 * old + 3)]);
+        xlat[old] = new as u8;
+    }
+
+    Ok((sorted, xlat))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{FlicFile,Raster,RasterMut};
+    use ::flic::tests::{TempFile,write_test_flic};
+    use super::*;
+
+    fn grey_pal() -> Vec<u8> {
+        (0..(3 * 256)).map(|i| (i / 3) as u8).collect()
+    }
+
+    #[test]
+    fn test_nearest_cache() {
+        let pal = [
+            0x00, 0x00, 0x00,
+            0x80, 0x80, 0x80,
+            0xFF, 0x00, 0x00,
+            0x80, 0x80, 0x80 ];
+
+        let mut cache = NearestCache::new(&pal);
+        assert_eq!(cache.nearest(0x80, 0x80, 0x80), 1);
+        assert_eq!(cache.nearest(0xF0, 0x10, 0x10), 2);
+        assert_eq!(cache.nearest(0x10, 0x10, 0x10), 0);
+        assert_eq!(cache.nearest(0x10, 0x10, 0x10), 0);
+        assert_eq!(nearest_color(&pal, 0x70, 0x70, 0x90), 1);
+    }
+
+    #[test]
+    fn test_remap_raster() {
+        let src_pal = grey_pal();
+        let mut dst_pal = vec![0; 3 * 256];
+        for (i, e) in dst_pal.chunks_mut(3).enumerate() {
+            let v = (255 - i) as u8;
+            e.copy_from_slice(&[v, v, v]);
+        }
+
+        let xlat = make_xlat(&src_pal, &dst_pal);
+        assert_eq!(xlat[0], 255);
+        assert_eq!(xlat[255], 0);
+
+        let mut buf = [ 9, 0, 1, 2, 9, 9 ];
+        let mut pal = src_pal.clone();
+        {
+            let mut raster = RasterMut::with_offset(1, 0, 2, 2, 3, &mut buf, &mut pal);
+            remap_raster(&mut raster, &dst_pal).expect("remap");
+            assert!(remap_raster(&mut raster, &dst_pal[3..]).is_err());
+        }
+        assert_eq!(buf, [ 9, 255, 254, 2, 246, 246 ]);
+        assert_eq!(pal, dst_pal);
+    }
+
+    #[test]
+    fn test_merge_palettes() {
+        let a_pal = grey_pal();
+        let mut b_pal = vec![0; 3 * 256];
+        b_pal[0..3].copy_from_slice(&[0x05, 0x05, 0x05]);
+        b_pal[3..6].copy_from_slice(&[0xFF, 0x00, 0x00]);
+
+        let buf = [ 4, 5, 6, 7 ];
+        let mut a_used = [false; 256];
+        mark_used(&Raster::new(2, 2, &buf, &a_pal), &mut a_used);
+        assert_eq!(a_used.iter().filter(|&&u| u).count(), 4);
+
+        let mut b_used = [false; 256];
+        b_used[0] = true;
+        b_used[1] = true;
+
+        let (pal, xlat) = merge_palettes(&a_pal, &a_used, &b_pal, &b_used).expect("merge");
+        assert_eq!(xlat[0], 5);
+        assert_eq!(xlat[1], 0);
+        assert_eq!(&pal[0..3], &[0xFF, 0x00, 0x00]);
+        assert_eq!(&pal[12..24], &a_pal[12..24]);
+
+        let all = [true; 256];
+        assert!(merge_palettes(&a_pal, &all, &b_pal, &b_used).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_luminance() {
+        let mut pal = grey_pal();
+        pal[0..3].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+
+        // Entries 0 and 255 are both white, and keep their order.
+        let (sorted, xlat) = sort_by_luminance(&pal).expect("sort");
+        assert_eq!(xlat[0], 254);
+        assert_eq!(xlat[255], 255);
+        assert_eq!(xlat[1], 0);
+        assert_eq!(&sorted[0..3], &[1, 1, 1]);
+        assert_eq!(&sorted[(3 * 255)..], &[0xFF, 0xFF, 0xFF]);
+
+        assert!(sort_by_luminance(&pal[3..]).is_err());
+    }
+
+    #[test]
+    fn test_remap_flic() {
+        const SCREEN_W: usize = 8;
+        const SCREEN_H: usize = 4;
+        let buf0: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 4) as u8).collect();
+        let buf1: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x % 3) as u8).collect();
+        let pal = grey_pal();
+        let mut new_pal = vec![0; 3 * 256];
+        for (i, e) in new_pal.chunks_mut(3).enumerate() {
+            let v = (255 - i) as u8;
+            e.copy_from_slice(&[v, v, v]);
+        }
+
+        let src = TempFile::new("remap-flic-src.flc");
+        let dst = TempFile::new("remap-flic-dst.flc");
+
+        write_test_flic(&src, &[
+                Raster::new(SCREEN_W, SCREEN_H, &buf0, &pal),
+                Raster::new(SCREEN_W, SCREEN_H, &buf1, &pal) ]);
+
+        let used = used_colors_in_flic(&src).expect("used");
+        assert_eq!(used.iter().filter(|&&u| u).count(), 4);
+
+        remap_flic(&src, &dst, &new_pal).expect("remap");
+
+        let mut flic = FlicFile::open(&dst).expect("open");
+        assert_eq!(flic.frame_count(), 2);
+        let mut frame = flic.new_frame();
+        flic.read_next_frame_into(&mut frame).expect("frame 0");
+        flic.read_next_frame_into(&mut frame).expect("frame 1");
+        assert_eq!(frame.pal(), &new_pal[..]);
+        assert!(frame.buf().iter().zip(&buf1).all(|(&d, &s)| d == 255 - s));
+
+        // Shift the pixels through a table instead.
+        let mut xlat = [0; 256];
+        for (c, x) in xlat.iter_mut().enumerate() {
+            *x = (c + 10) as u8;
+        }
+        remap_flic_with_xlat(&src, &dst, &xlat, &pal).expect("remap");
+
+        let mut flic = FlicFile::open(&dst).expect("open");
+        let mut frame = flic.new_frame();
+        flic.read_next_frame_into(&mut frame).expect("frame 0");
+        assert_eq!(frame.pal(), &pal[..]);
+        assert!(frame.buf().iter().zip(&buf0).all(|(&d, &s)| d == s + 10));
+
+        // Remap the source in place.
+        remap_flic(&src, &src, &new_pal).expect("remap");
+
+        let mut flic = FlicFile::open(&src).expect("open");
+        assert_eq!(flic.frame_count(), 2);
+        let mut frame = flic.new_frame();
+        flic.read_next_frame_into(&mut frame).expect("frame 0");
+        assert_eq!(frame.pal(), &new_pal[..]);
+        assert!(frame.buf().iter().zip(&buf0).all(|(&d, &s)| d == 255 - s));
+    }
+}
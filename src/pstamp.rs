@@ -7,8 +7,68 @@ use byteorder::{ReadBytesExt,WriteBytesExt};
 use ::{FlicError,FlicResult,Raster,RasterMut};
 use codec::*;
 
+/// Postage stamp downscaling filter.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum PstampFilter {
+    /// Take the nearest source pixel.  Fast, but aliases badly on
+    /// detailed frames.
+    Nearest,
+
+    /// Average the colours of the source pixels covered by each
+    /// postage stamp pixel.
+    Average,
+}
+
+/// Options for writing postage stamps.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct PstampOptions {
+    /// Maximum postage stamp width.  The frame's aspect ratio is
+    /// preserved.  Zero disables postage stamps.
+    pub max_w: u16,
+
+    /// Maximum postage stamp height.  Zero disables postage stamps.
+    pub max_h: u16,
+
+    /// Downscaling filter.
+    pub filter: PstampFilter,
+
+    /// Dither into the six-cube palette.
+    pub dither: bool,
+}
+
+impl Default for PstampOptions {
+    fn default() -> Self {
+        PstampOptions {
+            max_w: STANDARD_PSTAMP_W,
+            max_h: STANDARD_PSTAMP_H,
+            filter: PstampFilter::Nearest,
+            dither: false,
+        }
+    }
+}
+
+/// A decoded postage stamp, using the six-cube palette.
+#[derive(Clone,Debug)]
+pub struct Thumbnail {
+    /// Thumbnail width.
+    pub w: usize,
+
+    /// Thumbnail height.
+    pub h: usize,
+
+    /// The postage stamp size stored in the FLI_PSTAMP chunk, if
+    /// the FLIC has one.
+    pub native_size: Option<(usize, usize)>,
+
+    /// Pixels, w x h.
+    pub buf: Vec<u8>,
+
+    /// Palette, 256 RGB entries.
+    pub pal: Vec<u8>,
+}
+
 /// FLIC postage stamp creator.
-pub struct PostageStamp<'a> {
+pub struct PostageStamp<'r, 'a: 'r> {
     flic_w: usize,
     flic_h: usize,
     have_image: bool,
@@ -16,10 +76,36 @@ pub struct PostageStamp<'a> {
     have_xlat256: bool,
     apply_xlat256: bool,
     xlat256: [u8; 256],
-    dst: &'a mut RasterMut<'a>,
+    dst: &'r mut RasterMut<'a>,
+}
+
+impl Thumbnail {
+    /// Allocate a blank w x h thumbnail.
+    pub fn new(w: usize, h: usize, native_size: Option<(usize, usize)>)
+            -> Self {
+        assert!(w > 0 && h > 0);
+
+        Thumbnail {
+            w: w,
+            h: h,
+            native_size: native_size,
+            buf: vec![0; w.checked_mul(h).expect("overflow")],
+            pal: vec![0; 3 * 256],
+        }
+    }
+
+    /// Borrow the thumbnail as a raster.
+    pub fn as_raster<'a>(&'a self) -> Raster<'a> {
+        Raster::new(self.w, self.h, &self.buf, &self.pal)
+    }
+
+    /// Borrow the thumbnail as a mutable raster.
+    pub fn as_raster_mut<'a>(&'a mut self) -> RasterMut<'a> {
+        RasterMut::new(self.w, self.h, &mut self.buf, &mut self.pal)
+    }
 }
 
-impl<'a> PostageStamp<'a> {
+impl<'r, 'a> PostageStamp<'r, 'a> {
     /// Allocate a new postage stamp creator.
     ///
     /// # Examples
@@ -36,7 +122,7 @@ impl<'a> PostageStamp<'a> {
     ///
     /// flic::pstamp::PostageStamp::new(SCREEN_W, SCREEN_H, &mut raster);
     /// ```
-    pub fn new(flic_w: usize, flic_h: usize, dst: &'a mut RasterMut<'a>)
+    pub fn new(flic_w: usize, flic_h: usize, dst: &'r mut RasterMut<'a>)
             -> Self {
         assert!(flic_w > 0 && flic_h > 0);
 
@@ -131,6 +217,22 @@ impl<'a> PostageStamp<'a> {
 pub fn get_pstamp_size(
         max_w: u16, max_h: u16, w: u16, h: u16)
         -> (u16, u16) {
+    get_pstamp_size_for(max_w, max_h, w as usize, h as usize)
+}
+
+/// Get the postage stamp size for an image of any size.
+///
+/// # Examples
+///
+/// ```
+/// use flic::pstamp::get_pstamp_size_for;
+///
+/// assert_eq!(get_pstamp_size_for(100, 63, 320, 200), (100, 63));
+/// assert_eq!(get_pstamp_size_for(100, 63, 200000, 1000), (100, 1));
+/// ```
+pub fn get_pstamp_size_for(
+        max_w: u16, max_h: u16, w: usize, h: usize)
+        -> (u16, u16) {
     if max_w <= 0 || max_h <= 0 || w <= 0 || h <= 0 {
         return (0, 0);
     }
@@ -138,11 +240,13 @@ pub fn get_pstamp_size(
     let mut scaled_w;
     let mut scaled_h;
 
-    if (w as u32) * (max_h as u32) / (h as u32) > (max_w as u32) {
+    // The scaled size is no larger than max_w x max_h, so the
+    // results fit in u16.  Use u128 so the products cannot overflow.
+    if (w as u128) * (max_h as u128) / (h as u128) > (max_w as u128) {
         scaled_w = max_w;
-        scaled_h = ((h as u32) * (max_w as u32) / (w as u32)) as u16;
+        scaled_h = ((h as u128) * (max_w as u128) / (w as u128)) as u16;
     } else {
-        scaled_w = ((w as u32) * (max_h as u32) / (h as u32)) as u16;
+        scaled_w = ((w as u128) * (max_h as u128) / (h as u128)) as u16;
         scaled_h = max_h;
     }
 
@@ -156,6 +260,15 @@ pub fn get_pstamp_size(
     (scaled_w, scaled_h)
 }
 
+/// Get the postage stamp size stored in a FLI_PSTAMP chunk.
+pub fn read_pstamp_size(src: &[u8])
+        -> FlicResult<(usize, usize)> {
+    let mut r = Cursor::new(src);
+    let height = r.read_u16::<LE>()? as usize;
+    let width = r.read_u16::<LE>()? as usize;
+    Ok((width, height))
+}
+
 /// Decode a FLI_PSTAMP chunk.
 ///
 /// Returns true if the postage stamp has been created.
@@ -205,22 +318,23 @@ fn decode_fli_pstamp(
     }
 }
 
-/// Write the postage stamp chunk.
+/// Write the postage stamp chunk, using the standard size.
 pub fn write_pstamp_data<W: Write + Seek>(
         next: &Raster, w: &mut W)
         -> FlicResult<usize> {
+    write_pstamp_data_with_options(next, &PstampOptions::default(), w)
+}
+
+/// Write the postage stamp chunk with the given size and filtering.
+pub fn write_pstamp_data_with_options<W: Write + Seek>(
+        next: &Raster, options: &PstampOptions, w: &mut W)
+        -> FlicResult<usize> {
     const SIZE_OF_CHUNK_ID: usize = 6;
     const SIZE_OF_SUB_CHUNK: usize = SIZE_OF_CHUNK_ID;
     const SIZE_OF_FULL_CHUNK: usize = SIZE_OF_CHUNK_ID + 6 + SIZE_OF_SUB_CHUNK;
 
-    if next.w > ::std::u16::MAX as usize || next.h > ::std::u16::MAX as usize {
-        // We can still write a postage stamp for huge images, but
-        // get_pstamp_size() is not smart enough right now.
-        return Err(FlicError::ExceededLimit);
-    }
-
-    let (pstamp_w, pstamp_h) = get_pstamp_size(
-            STANDARD_PSTAMP_W, STANDARD_PSTAMP_H, next.w as u16, next.h as u16);
+    let (pstamp_w, pstamp_h) = get_pstamp_size_for(
+            options.max_w, options.max_h, next.w, next.h);
 
     if pstamp_w <= 0 || pstamp_h <= 0 || can_encode_fli_black(next) {
         return Ok(0);
@@ -237,8 +351,9 @@ pub fn write_pstamp_data<W: Write + Seek>(
     let mut xlat256 = [0; 256];
     make_pstamp_xlat256(&next.pal, &mut xlat256);
 
-    // FPS_XLAT256
-    if chunk_magic == FPS_COPY && (next.w * next.h < chunk_size as usize) {
+    // FPS_XLAT256, unless dithering.
+    if chunk_magic == FPS_COPY && !options.dither
+            && next.w.checked_mul(next.h).map_or(false, |n| n < chunk_size) {
         chunk_size = 256;
         chunk_magic = FPS_XLAT256;
 
@@ -247,8 +362,16 @@ pub fn write_pstamp_data<W: Write + Seek>(
 
     // FPS_BRUN/FPS_COPY.
     if chunk_magic == FPS_COPY {
-        let pstamp_buf = prepare_pstamp(
-                next, &xlat256, pstamp_w as usize, pstamp_h as usize);
+        let dst_w = pstamp_w as usize;
+        let dst_h = pstamp_h as usize;
+        let pstamp_buf = match (options.filter, options.dither) {
+            (PstampFilter::Nearest, false) =>
+                prepare_pstamp(next, &xlat256, dst_w, dst_h),
+            (PstampFilter::Nearest, true) =>
+                map_pstamp_rgb(&sample_pstamp_rgb(next, dst_w, dst_h), dst_w, dst_h, true),
+            (PstampFilter::Average, dither) =>
+                map_pstamp_rgb(&average_pstamp_rgb(next, dst_w, dst_h), dst_w, dst_h, dither),
+        };
         let pstamp = Raster::new(
                 pstamp_w as usize, pstamp_h as usize, &pstamp_buf, &next.pal);
 
@@ -289,3 +412,43 @@ pub fn write_pstamp_data<W: Write + Seek>(
 
     Ok((pos2 - pos0) as usize)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use byteorder::LittleEndian as LE;
+    use byteorder::ReadBytesExt;
+    use ::Raster;
+    use codec::FLI_PSTAMP;
+    use super::*;
+
+    #[test]
+    fn test_get_pstamp_size() {
+        assert_eq!(get_pstamp_size(100, 63, 320, 200), (100, 63));
+        assert_eq!(get_pstamp_size(100, 63, 200, 320), (39, 63));
+        assert_eq!(get_pstamp_size(100, 63, 0, 200), (0, 0));
+
+        assert_eq!(get_pstamp_size_for(100, 63, 1 << 20, 1 << 18), (100, 25));
+        assert_eq!(get_pstamp_size_for(100, 63, 1000, 1 << 20), (1, 63));
+        assert_eq!(get_pstamp_size_for(100, 63, ::std::usize::MAX, ::std::usize::MAX), (63, 63));
+    }
+
+    #[test]
+    fn test_write_pstamp_data_wide() {
+        const SCREEN_W: usize = 70000;
+        const SCREEN_H: usize = 2;
+        let buf: Vec<u8> = (0..(SCREEN_W * SCREEN_H)).map(|x| (x / 1000) as u8).collect();
+        let pal: Vec<u8> = (0..(3 * 256)).map(|x| x as u8).collect();
+        let raster = Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
+
+        let mut w = Cursor::new(Vec::new());
+        let size = write_pstamp_data(&raster, &mut w).expect("pstamp");
+        assert!(size > 0);
+
+        let mut r = Cursor::new(w.into_inner());
+        assert_eq!(r.read_u32::<LE>().expect("size") as usize, size);
+        assert_eq!(r.read_u16::<LE>().expect("magic"), FLI_PSTAMP);
+        assert_eq!(r.read_u16::<LE>().expect("height"), 1);
+        assert_eq!(r.read_u16::<LE>().expect("width"), 100);
+    }
+}
@@ -0,0 +1,533 @@
+//! Truecolor to 256-colour quantization.
+//!
+//! Palettes are built by median cut over a 15-bit colour histogram,
+//! unless the image already has few enough distinct colours, in
+//! which case they are used exactly.
+//!
+//! Quantizing each frame separately makes the palette and pixel
+//! indices change every frame, which compresses poorly.  For
+//! animations, build one global palette for the whole sequence with
+//! `make_global_palette`, or a slowly evolving palette with
+//! `plan_sliding_palettes`.
+
+use std::collections::HashSet;
+
+use ::{FlicError,FlicResult,RasterMut};
+use ::palette::NearestCache;
+
+pub use ::palette::nearest_color;
+
+/// Layout of a truecolor pixel.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum TruecolorFormat {
+    /// Three bytes per pixel: red, green, blue.
+    Rgb24,
+
+    /// Four bytes per pixel: red, green, blue, alpha.  The alpha
+    /// channel is ignored.
+    Rgba32,
+}
+
+/// Dithering applied when mapping truecolor pixels to the palette.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Dither {
+    /// Map each pixel to the nearest colour.
+    None,
+
+    /// Floyd-Steinberg error diffusion.
+    FloydSteinberg,
+
+    /// 4x4 ordered (Bayer) dithering.  Unlike error diffusion, the
+    /// pattern is stable from frame to frame, so it compresses well.
+    Ordered,
+}
+
+/// Histogram entry: a 15-bit colour with pixel count and channel sums.
+#[derive(Clone,Copy)]
+struct HistEntry {
+    rgb: [u8; 3],
+    count: u64,
+    sum: [u64; 3],
+}
+
+const BAYER4X4: [[i32; 4]; 4] = [
+    [  0,  8,  2, 10 ],
+    [ 12,  4, 14,  6 ],
+    [  3, 11,  1,  9 ],
+    [ 15,  7, 13,  5 ] ];
+
+impl TruecolorFormat {
+    /// Number of bytes per pixel.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            TruecolorFormat::Rgb24 => 3,
+            TruecolorFormat::Rgba32 => 4,
+        }
+    }
+}
+
+/*--------------------------------------------------------------*/
+
+/// Quantize a truecolor image into the raster, creating a palette
+/// of up to 256 colours.
+///
+/// The source image is packed, with `dst.w` pixels per row and
+/// `dst.h` rows.
+///
+/// # Examples
+///
+/// ```
+/// use flic::quantize::{Dither,TruecolorFormat};
+///
+/// const SCREEN_W: usize = 320;
+/// const SCREEN_H: usize = 200;
+/// let rgb = vec![0; 3 * SCREEN_W * SCREEN_H];
+/// let mut buf = [0; SCREEN_W * SCREEN_H];
+/// let mut pal = [0; 3 * 256];
+/// let mut raster = flic::RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal);
+///
+/// flic::quantize::quantize(&rgb, TruecolorFormat::Rgb24, Dither::None, &mut raster);
+/// ```
+pub fn quantize(
+        src: &[u8], format: TruecolorFormat, dither: Dither, dst: &mut RasterMut)
+        -> FlicResult<()> {
+    let bpp = format.bytes_per_pixel();
+    if src.len() != bpp * dst.w * dst.h {
+        return Err(FlicError::BadInput);
+    }
+
+    let num_cols = make_palette(src, format, 256, &mut dst.pal);
+    remap(src, format, dither, num_cols, dst)
+}
+
+/// Build a palette of up to `max_cols` colours for the truecolor
+/// image, using median cut.  Unused palette entries are set to black.
+///
+/// Returns the number of colours used.
+pub fn make_palette(
+        src: &[u8], format: TruecolorFormat, max_cols: usize, pal: &mut [u8])
+        -> usize {
+    make_global_palette(&[src], format, max_cols, pal)
+}
+
+/// Build a single palette of up to `max_cols` colours for a sequence
+/// of truecolor frames, using median cut.  Unused palette entries are
+/// set to black.
+///
+/// Returns the number of colours used.
+///
+/// # Examples
+///
+/// ```
+/// use flic::quantize::TruecolorFormat;
+///
+/// let frame1 = [ 0xFF, 0, 0 ];
+/// let frame2 = [ 0, 0xFF, 0 ];
+/// let mut pal = [0; 3 * 256];
+///
+/// let n = flic::quantize::make_global_palette(
+///         &[&frame1, &frame2], TruecolorFormat::Rgb24, 256, &mut pal);
+/// assert_eq!(n, 2);
+/// ```
+pub fn make_global_palette(
+        srcs: &[&[u8]], format: TruecolorFormat, max_cols: usize, pal: &mut [u8])
+        -> usize {
+    let bpp = format.bytes_per_pixel();
+    let max_cols = ::std::cmp::min(max_cols, pal.len() / 3);
+
+    for e in pal.iter_mut() {
+        *e = 0;
+    }
+
+    if max_cols <= 0 {
+        return 0;
+    }
+
+    // Use the colours exactly if there are few enough of them.
+    let mut exact = HashSet::new();
+    for px in srcs.iter().flat_map(|src| src.chunks(bpp)) {
+        exact.insert([px[0], px[1], px[2]]);
+        if exact.len() > max_cols {
+            break;
+        }
+    }
+
+    if exact.len() <= max_cols {
+        let mut cols: Vec<[u8; 3]> = exact.into_iter().collect();
+        cols.sort();
+        for (c, rgb) in cols.iter().enumerate() {
+            pal[(3 * c)..(3 * c + 3)].copy_from_slice(rgb);
+        }
+        return cols.len();
+    }
+
+    // Median cut over the 15-bit histogram.
+    let mut hist: Vec<HistEntry> = (0..32768)
+        .map(|i| HistEntry {
+            rgb: [(i >> 10) as u8, ((i >> 5) & 0x1F) as u8, (i & 0x1F) as u8],
+            count: 0,
+            sum: [0; 3],
+        })
+        .collect();
+
+    for px in srcs.iter().flat_map(|src| src.chunks(bpp)) {
+        let e = &mut hist[rgb15(px[0], px[1], px[2])];
+        e.count = e.count + 1;
+        for i in 0..3 {
+            e.sum[i] = e.sum[i] + px[i] as u64;
+        }
+    }
+
+    hist.retain(|e| e.count > 0);
+
+    let mut boxes: Vec<(usize, usize)> = vec![(0, hist.len())];
+    while boxes.len() < max_cols {
+        // Split the box with the widest channel range, weighted by
+        // the number of pixels it contains.
+        let mut best = None;
+        let mut best_score = 0;
+        for (b, &(start, end)) in boxes.iter().enumerate() {
+            if end - start < 2 {
+                continue;
+            }
+
+            let (axis, range) = box_range(&hist[start..end]);
+            let count: u64 = hist[start..end].iter().map(|e| e.count).sum();
+            let score = (range as u64) * count;
+            if score > best_score {
+                best = Some((b, axis));
+                best_score = score;
+            }
+        }
+
+        let (b, axis) = match best {
+            Some(x) => x,
+            None => break,
+        };
+
+        let (start, end) = boxes[b];
+        hist[start..end].sort_by_key(|e| e.rgb[axis]);
+
+        // Split at the weighted median, keeping both halves non-empty.
+        let total: u64 = hist[start..end].iter().map(|e| e.count).sum();
+        let mut acc = 0;
+        let mut mid = start + 1;
+        for i in start..(end - 1) {
+            acc = acc + hist[i].count;
+            mid = i + 1;
+            if 2 * acc >= total {
+                break;
+            }
+        }
+
+        boxes[b] = (start, mid);
+        boxes.push((mid, end));
+    }
+
+    for (c, &(start, end)) in boxes.iter().enumerate() {
+        let mut count = 0;
+        let mut sum = [0; 3];
+        for e in &hist[start..end] {
+            count = count + e.count;
+            for i in 0..3 {
+                sum[i] = sum[i] + e.sum[i];
+            }
+        }
+
+        for i in 0..3 {
+            pal[3 * c + i] = ((sum[i] + count / 2) / count) as u8;
+        }
+    }
+
+    boxes.len()
+}
+
+/// Plan a slowly evolving palette for each frame of a sequence of
+/// truecolor frames.
+///
+/// Each frame's palette is built from the frames within `window`
+/// frames of it, then merged into the previous frame's palette with
+/// `evolve_palette`, so that palette entries only change where
+/// needed.  Returns one 256-colour palette per frame.
+///
+/// A window larger than the sequence gives a single global palette.
+pub fn plan_sliding_palettes(
+        srcs: &[&[u8]], format: TruecolorFormat, window: usize, threshold: u16)
+        -> Vec<Vec<u8>> {
+    let mut pals: Vec<Vec<u8>> = Vec::with_capacity(srcs.len());
+    let mut candidate = vec![0; 3 * 256];
+
+    for i in 0..srcs.len() {
+        let start = i.saturating_sub(window);
+        let end = ::std::cmp::min(srcs.len(), i.saturating_add(window).saturating_add(1));
+        let num_cols = make_global_palette(&srcs[start..end], format, 256, &mut candidate);
+
+        let mut pal = vec![0; 3 * 256];
+        match pals.last() {
+            Some(prev) =>
+                evolve_palette_unchecked(prev, &candidate[0..(3 * num_cols)], threshold, &mut pal),
+            None =>
+                pal.copy_from_slice(&candidate),
+        }
+
+        pals.push(pal);
+    }
+
+    pals
+}
+
+/// Merge the candidate colours into the previous palette, changing
+/// as few entries as possible.
+///
+/// Each candidate colour is matched to a distinct entry of the
+/// previous palette, closest pairs first.  If the pair is within
+/// `threshold` (Euclidean distance in RGB), the previous colour is
+/// kept; otherwise the entry is replaced by the candidate colour.
+/// Entries that are not matched keep their previous colour.
+pub fn evolve_palette(prev: &[u8], candidate: &[u8], threshold: u16, dst: &mut [u8])
+        -> FlicResult<()> {
+    if prev.len() % 3 != 0 || candidate.len() % 3 != 0 {
+        return Err(FlicError::BadInput);
+    } else if prev.len() != dst.len() || candidate.len() > prev.len() {
+        return Err(FlicError::BadInput);
+    }
+
+    evolve_palette_unchecked(prev, candidate, threshold, dst);
+    Ok(())
+}
+
+fn evolve_palette_unchecked(prev: &[u8], candidate: &[u8], threshold: u16, dst: &mut [u8]) {
+    dst.copy_from_slice(prev);
+
+    let num_prev = prev.len() / 3;
+    let num_cand = candidate.len() / 3;
+    let mut pairs = Vec::with_capacity(num_prev * num_cand);
+    for (c, e) in candidate.chunks(3).enumerate() {
+        for (p, f) in prev.chunks(3).enumerate() {
+            let dist = (0..3)
+                .map(|i| {
+                    let d = e[i] as i32 - f[i] as i32;
+                    (d * d) as u32
+                })
+                .sum::<u32>();
+            pairs.push((dist, c, p));
+        }
+    }
+
+    pairs.sort();
+
+    let threshold2 = (threshold as u32) * (threshold as u32);
+    let mut cand_used = vec![false; num_cand];
+    let mut prev_used = vec![false; num_prev];
+    for (dist, c, p) in pairs {
+        if cand_used[c] || prev_used[p] {
+            continue;
+        }
+
+        cand_used[c] = true;
+        prev_used[p] = true;
+        if dist > threshold2 {
+            dst[(3 * p)..(3 * p + 3)].copy_from_slice(&candidate[(3 * c)..(3 * c + 3)]);
+        }
+    }
+}
+
+/// Map a truecolor image to the first `num_cols` entries of the
+/// raster's palette, with optional dithering.
+///
+/// The source image is packed, with `dst.w` pixels per row and
+/// `dst.h` rows.
+pub fn remap(
+        src: &[u8], format: TruecolorFormat, dither: Dither, num_cols: usize,
+        dst: &mut RasterMut)
+        -> FlicResult<()> {
+    let bpp = format.bytes_per_pixel();
+    if src.len() != bpp * dst.w * dst.h {
+        return Err(FlicError::BadInput);
+    } else if num_cols <= 0 || 3 * num_cols > dst.pal.len() {
+        return Err(FlicError::BadInput);
+    }
+
+    let w = dst.w;
+    let mut cache = NearestCache::new(&dst.pal[0..(3 * num_cols)]);
+
+    // Floyd-Steinberg error for this row and the next, with one
+    // pixel of padding on either side.
+    let mut err0 = vec![[0i32; 3]; w + 2];
+    let mut err1 = vec![[0i32; 3]; w + 2];
+
+    let start = dst.stride * dst.y;
+    let end = dst.stride * (dst.y + dst.h);
+    for (y, (src_row, dst_row)) in src.chunks(bpp * w)
+            .zip(dst.buf[start..end].chunks_mut(dst.stride))
+            .enumerate() {
+        let dst_row = &mut dst_row[dst.x..(dst.x + w)];
+
+        for (x, (px, e)) in src_row.chunks(bpp).zip(dst_row.iter_mut()).enumerate() {
+            let mut rgb = [px[0] as i32, px[1] as i32, px[2] as i32];
+
+            match dither {
+                Dither::None => (),
+                Dither::FloydSteinberg =>
+                    for i in 0..3 {
+                        rgb[i] = rgb[i] + err0[x + 1][i] / 16;
+                    },
+                Dither::Ordered => {
+                    let offset = 2 * BAYER4X4[y % 4][x % 4] - 15;
+                    for i in 0..3 {
+                        rgb[i] = rgb[i] + offset;
+                    }
+                },
+            }
+
+            let r = clamp_u8(rgb[0]);
+            let g = clamp_u8(rgb[1]);
+            let b = clamp_u8(rgb[2]);
+            let c = cache.nearest(r, g, b);
+            *e = c;
+
+            if dither == Dither::FloydSteinberg {
+                let pal = &cache.pal()[(3 * c as usize)..(3 * c as usize + 3)];
+                for i in 0..3 {
+                    let q = rgb[i] - pal[i] as i32;
+                    err0[x + 2][i] = err0[x + 2][i] + 7 * q;
+                    err1[x + 0][i] = err1[x + 0][i] + 3 * q;
+                    err1[x + 1][i] = err1[x + 1][i] + 5 * q;
+                    err1[x + 2][i] = err1[x + 2][i] + 1 * q;
+                }
+            }
+        }
+
+        if dither == Dither::FloydSteinberg {
+            ::std::mem::swap(&mut err0, &mut err1);
+            for e in err1.iter_mut() {
+                *e = [0; 3];
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/*--------------------------------------------------------------*/
+
+/// The channel with the widest range of values in the histogram
+/// entries, and that range.
+fn box_range(hist: &[HistEntry])
+        -> (usize, u8) {
+    let mut lo = [::std::u8::MAX; 3];
+    let mut hi = [0; 3];
+    for e in hist {
+        for i in 0..3 {
+            lo[i] = ::std::cmp::min(lo[i], e.rgb[i]);
+            hi[i] = ::std::cmp::max(hi[i], e.rgb[i]);
+        }
+    }
+
+    (0..3).map(|i| (i, hi[i] - lo[i]))
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn rgb15(r: u8, g: u8, b: u8)
+        -> usize {
+    ((r as usize >> 3) << 10) | ((g as usize >> 3) << 5) | (b as usize >> 3)
+}
+
+fn clamp_u8(x: i32)
+        -> u8 {
+    if x < 0 {
+        0
+    } else if x > 255 {
+        255
+    } else {
+        x as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::RasterMut;
+    use super::{Dither,TruecolorFormat,evolve_palette,make_palette,plan_sliding_palettes,quantize};
+
+    #[test]
+    fn test_make_palette_exact() {
+        let rgb = [ 10, 20, 30,  10, 20, 30,  200, 100, 0 ];
+        let mut pal = [0xFF; 3 * 256];
+        let n = make_palette(&rgb, TruecolorFormat::Rgb24, 256, &mut pal);
+        assert_eq!(n, 2);
+        assert_eq!(&pal[0..6], &[ 10, 20, 30, 200, 100, 0 ]);
+        assert!(pal[6..].iter().all(|&e| e == 0));
+    }
+
+    #[test]
+    fn test_quantize() {
+        const SCREEN_W: usize = 64;
+        const SCREEN_H: usize = 64;
+
+        // A smooth gradient with more than 256 colours.
+        let mut rgba = Vec::new();
+        for y in 0..SCREEN_H {
+            for x in 0..SCREEN_W {
+                rgba.extend_from_slice(&[ (4 * x) as u8, (4 * y) as u8, (2 * (x + y)) as u8, 0xFF ]);
+            }
+        }
+
+        for &dither in &[ Dither::None, Dither::FloydSteinberg, Dither::Ordered ] {
+            let mut buf = [0; SCREEN_W * SCREEN_H];
+            let mut pal = [0; 3 * 256];
+            {
+                let mut raster = RasterMut::new(SCREEN_W, SCREEN_H, &mut buf, &mut pal);
+                quantize(&rgba, TruecolorFormat::Rgba32, dither, &mut raster).expect("quantize");
+            }
+
+            // Every pixel should be reasonably close to the source.
+            for (px, &c) in rgba.chunks(4).zip(buf.iter()) {
+                let c = c as usize;
+                for i in 0..3 {
+                    let d = px[i] as i32 - pal[3 * c + i] as i32;
+                    assert!(d.abs() <= 40, "{:?} {} {}", dither, px[i], pal[3 * c + i]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_evolve_palette() {
+        let prev = [ 0, 0, 0,  100, 100, 100,  200, 0, 0 ];
+        let cand = [ 102, 99, 100,  0, 0, 250 ];
+        let expected = [ 0, 0, 250,  100, 100, 100,  200, 0, 0 ];
+        let mut dst = [0; 9];
+
+        // The close match keeps its old colour and slot, the new
+        // colour replaces the nearest remaining entry.
+        evolve_palette(&prev, &cand, 8, &mut dst).expect("evolve");
+        assert_eq!(&dst[..], &expected[..]);
+
+        // Mismatched sizes are rejected.
+        assert!(evolve_palette(&prev, &cand, 8, &mut dst[0..6]).is_err());
+        assert!(evolve_palette(&cand, &prev, 8, &mut dst[0..6]).is_err());
+        assert!(evolve_palette(&prev, &cand[0..4], 8, &mut dst).is_err());
+    }
+
+    #[test]
+    fn test_plan_sliding_palettes() {
+        let frames: Vec<Vec<u8>> = (0..4)
+            .map(|i| vec![ 10, 20, 30,  (50 * i) as u8, 0, 0 ])
+            .collect();
+        let srcs: Vec<&[u8]> = frames.iter().map(|f| &f[..]).collect();
+
+        let pals = plan_sliding_palettes(&srcs, TruecolorFormat::Rgb24, 0, 0);
+        assert_eq!(pals.len(), 4);
+
+        // The unchanging colour keeps its palette entry.
+        let slot = pals[0].chunks(3).position(|e| e == &[ 10, 20, 30 ]).expect("colour");
+        for pal in &pals {
+            assert_eq!(&pal[(3 * slot)..(3 * slot + 3)], &[ 10, 20, 30 ]);
+        }
+
+        // A window covering the sequence gives a global palette.
+        let pals = plan_sliding_palettes(&srcs, TruecolorFormat::Rgb24, 4, 0);
+        assert!(pals.iter().all(|pal| pal == &pals[0]));
+    }
+}
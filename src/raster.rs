@@ -1,6 +1,100 @@
 //! Raster implementation.
 
-use ::{Raster,RasterMut};
+use ::{FlicError,FlicResult,Frame,Raster,RasterMut,Rect};
+
+impl Frame {
+    /// Allocate a new black frame with a black palette.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// const SCREEN_W: usize = 320;
+    /// const SCREEN_H: usize = 200;
+    ///
+    /// let frame = flic::Frame::new(SCREEN_W, SCREEN_H);
+    /// let raster = frame.as_raster();
+    /// ```
+    pub fn new(w: usize, h: usize)
+            -> Self {
+        assert!(w > 0 && h > 0);
+
+        Frame {
+            w: w,
+            h: h,
+            buf: vec![0; w.checked_mul(h).expect("overflow")],
+            pal: vec![0; 3 * 256],
+        }
+    }
+
+    /// Allocate a new frame, copying the raster's pixels and palette.
+    pub fn from_raster(src: &Raster)
+            -> Self {
+        let mut frame = Self::new(src.w, src.h);
+        frame.copy_from_raster(src);
+        frame
+    }
+
+    /// Frame width.
+    pub fn width(&self) -> usize {
+        self.w
+    }
+
+    /// Frame height.
+    pub fn height(&self) -> usize {
+        self.h
+    }
+
+    /// Screen buffer, w x h.
+    pub fn buf(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Mutable screen buffer, w x h.
+    pub fn buf_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+
+    /// Palette, 256 RGB entries.
+    pub fn pal(&self) -> &[u8] {
+        &self.pal
+    }
+
+    /// Mutable palette, 256 RGB entries.
+    pub fn pal_mut(&mut self) -> &mut [u8] {
+        &mut self.pal
+    }
+
+    /// Borrow the frame as a raster.
+    pub fn as_raster<'a>(&'a self) -> Raster<'a> {
+        Raster::new(self.w, self.h, &self.buf, &self.pal)
+    }
+
+    /// Borrow the frame as a mutable raster.
+    pub fn as_raster_mut<'a>(&'a mut self) -> RasterMut<'a> {
+        RasterMut::new(self.w, self.h, &mut self.buf, &mut self.pal)
+    }
+
+    /// Copy the raster's pixels and palette into the frame, which
+    /// must have the same size.
+    pub fn copy_from_raster(&mut self, src: &Raster) {
+        assert!(src.w == self.w && src.h == self.h);
+
+        let start = src.stride * src.y;
+        let end = src.stride * (src.y + src.h);
+        for (dst_row, src_row) in self.buf.chunks_mut(self.w)
+                .zip(src.buf[start..end].chunks(src.stride)) {
+            dst_row.copy_from_slice(&src_row[src.x..(src.x + src.w)]);
+        }
+
+        self.pal.copy_from_slice(src.pal);
+    }
+
+    /// Swap the contents of two frames, e.g. the previous and next
+    /// frames, without copying.
+    pub fn swap(&mut self, other: &mut Frame) {
+        ::std::mem::swap(self, other);
+    }
+}
 
 impl<'a> Raster<'a> {
     /// Allocate a new raster for the given screen buffer and palette
@@ -22,6 +116,23 @@ impl<'a> Raster<'a> {
         Self::with_offset(0, 0, w, h, w, buf, pal)
     }
 
+    /// Allocate a new raster for the given screen buffer and palette
+    /// memory slices, or return an error if they do not fit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut buf = [0; 16];
+    /// let mut pal = [0; 3 * 256];
+    ///
+    /// assert!(flic::Raster::try_new(4, 4, &buf, &pal).is_ok());
+    /// assert!(flic::Raster::try_new(8, 4, &buf, &pal).is_err());
+    /// ```
+    pub fn try_new(w: usize, h: usize, buf: &'a [u8], pal: &'a [u8])
+            -> FlicResult<Self> {
+        Self::try_with_offset(0, 0, w, h, w, buf, pal)
+    }
+
     /// Allocate a new raster for the given screen buffer and palette
     /// memory slices, with an offset and stride.
     ///
@@ -40,14 +151,54 @@ impl<'a> Raster<'a> {
             x: usize, y: usize, w: usize, h: usize, stride: usize,
             buf: &'a [u8], pal: &'a [u8])
             -> Self {
-        let x1 = x.checked_add(w).expect("overflow");
-        let y1 = y.checked_add(h).expect("overflow");
-        assert!(x < x1 && x1 <= stride && h > 0);
-        assert!(stride.checked_mul(y1).expect("overflow") <= buf.len());
-        assert!(pal.len() == 3 * 256);
+        match Self::try_with_offset(x, y, w, h, stride, buf, pal) {
+            Ok(raster) => raster,
+            Err(e) => panic!("{}", e),
+        }
+    }
 
-        Raster {
+    /// Allocate a new raster for the given screen buffer and palette
+    /// memory slices, with an offset and stride, or return an error
+    /// if they do not fit.
+    pub fn try_with_offset(
+            x: usize, y: usize, w: usize, h: usize, stride: usize,
+            buf: &'a [u8], pal: &'a [u8])
+            -> FlicResult<Self> {
+        check_raster(x, y, w, h, stride, buf.len(), pal.len())?;
+
+        Ok(Raster {
             x, y, w, h, stride, buf, pal,
+        })
+    }
+}
+
+impl<'a> Raster<'a> {
+    /// Create a view of the w x h region at (x, y), relative to this
+    /// raster.  The view shares the buffer and palette.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let buf = [0; 320 * 200];
+    /// let pal = [0; 3 * 256];
+    /// let screen = flic::Raster::new(320, 200, &buf, &pal);
+    ///
+    /// let left = screen.sub_raster(0, 0, 160, 200).expect("left");
+    /// let right = screen.sub_raster(160, 0, 160, 200).expect("right");
+    /// ```
+    pub fn sub_raster(&self, x: usize, y: usize, w: usize, h: usize)
+            -> FlicResult<Raster<'a>> {
+        check_sub_raster(self.w, self.h, x, y, w, h)?;
+        Raster::try_with_offset(
+                self.x + x, self.y + y, w, h, self.stride, self.buf, self.pal)
+    }
+
+    /// Get the colour index of the pixel at (x, y).
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u8> {
+        if x < self.w && y < self.h {
+            Some(self.buf[self.stride * (self.y + y) + (self.x + x)])
+        } else {
+            None
         }
     }
 }
@@ -72,6 +223,23 @@ impl<'a> RasterMut<'a> {
         Self::with_offset(0, 0, w, h, w, buf, pal)
     }
 
+    /// Allocate a new raster for the given screen buffer and palette
+    /// memory slices, or return an error if they do not fit.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut buf = [0; 16];
+    /// let mut pal = [0; 3 * 256];
+    ///
+    /// assert!(flic::RasterMut::try_new(4, 4, &mut buf, &mut pal).is_ok());
+    /// assert!(flic::RasterMut::try_new(8, 4, &mut buf, &mut pal).is_err());
+    /// ```
+    pub fn try_new(w: usize, h: usize, buf: &'a mut [u8], pal: &'a mut [u8])
+            -> FlicResult<Self> {
+        Self::try_with_offset(0, 0, w, h, w, buf, pal)
+    }
+
     /// Allocate a new raster for the given screen buffer and palette
     /// memory slices, with an offset and stride.
     ///
@@ -90,21 +258,207 @@ impl<'a> RasterMut<'a> {
             x: usize, y: usize, w: usize, h: usize, stride: usize,
             buf: &'a mut [u8], pal: &'a mut [u8])
             -> Self {
-        let x1 = x.checked_add(w).expect("overflow");
-        let y1 = y.checked_add(h).expect("overflow");
-        assert!(x < x1 && x1 <= stride && h > 0);
-        assert!(stride.checked_mul(y1).expect("overflow") <= buf.len());
-        assert!(pal.len() == 3 * 256);
+        match Self::try_with_offset(x, y, w, h, stride, buf, pal) {
+            Ok(raster) => raster,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Allocate a new raster for the given screen buffer and palette
+    /// memory slices, with an offset and stride, or return an error
+    /// if they do not fit.
+    pub fn try_with_offset(
+            x: usize, y: usize, w: usize, h: usize, stride: usize,
+            buf: &'a mut [u8], pal: &'a mut [u8])
+            -> FlicResult<Self> {
+        check_raster(x, y, w, h, stride, buf.len(), pal.len())?;
 
-        RasterMut {
+        Ok(RasterMut {
             x, y, w, h, stride, buf, pal,
+        })
+    }
+}
+
+impl<'a> RasterMut<'a> {
+    /// Create a read-only view of the whole raster.
+    pub fn as_raster<'b>(&'b self) -> Raster<'b> {
+        Raster {
+            x: self.x, y: self.y, w: self.w, h: self.h, stride: self.stride,
+            buf: self.buf, pal: self.pal,
         }
     }
+
+    /// Create a read-only view of the w x h region at (x, y),
+    /// relative to this raster.
+    pub fn sub_raster<'b>(&'b self, x: usize, y: usize, w: usize, h: usize)
+            -> FlicResult<Raster<'b>> {
+        self.as_raster().sub_raster(x, y, w, h)
+    }
+
+    /// Create a mutable view of the w x h region at (x, y),
+    /// relative to this raster.
+    pub fn sub_raster_mut<'b>(&'b mut self, x: usize, y: usize, w: usize, h: usize)
+            -> FlicResult<RasterMut<'b>> {
+        check_sub_raster(self.w, self.h, x, y, w, h)?;
+        RasterMut::try_with_offset(
+                self.x + x, self.y + y, w, h, self.stride, self.buf, self.pal)
+    }
+
+    /// Copy the source raster's pixels to (dx, dy), clipped to this
+    /// raster on all sides, so the offsets may be negative.  Pixels
+    /// of the transparent colour index, if any, are not copied.  The
+    /// palette is not copied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut buf = [0; 320 * 200];
+    /// let mut pal = [0; 3 * 256];
+    /// let cursor_buf = [1; 8 * 8];
+    /// let cursor_pal = [0; 3 * 256];
+    /// let mut screen = flic::RasterMut::new(320, 200, &mut buf, &mut pal);
+    /// let cursor = flic::Raster::new(8, 8, &cursor_buf, &cursor_pal);
+    ///
+    /// screen.blit_from(&cursor, 316, 100, Some(0));
+    /// ```
+    pub fn blit_from(&mut self, src: &Raster, dx: isize, dy: isize, transparent: Option<u8>) {
+        // Skip the source columns and rows left of or above this raster.
+        let sx = dx.min(0).unsigned_abs();
+        let sy = dy.min(0).unsigned_abs();
+        let dx = dx.max(0) as usize;
+        let dy = dy.max(0) as usize;
+        if sx >= src.w || sy >= src.h || dx >= self.w || dy >= self.h {
+            return;
+        }
+
+        let w = ::std::cmp::min(src.w - sx, self.w - dx);
+        let h = ::std::cmp::min(src.h - sy, self.h - dy);
+
+        for y in 0..h {
+            let src_start = src.stride * (src.y + sy + y) + (src.x + sx);
+            let dst_start = self.stride * (self.y + dy + y) + (self.x + dx);
+            let src_row = &src.buf[src_start..(src_start + w)];
+            let dst_row = &mut self.buf[dst_start..(dst_start + w)];
+
+            match transparent {
+                Some(t) =>
+                    for (d, &s) in dst_row.iter_mut().zip(src_row) {
+                        if s != t {
+                            *d = s;
+                        }
+                    },
+                None => dst_row.copy_from_slice(src_row),
+            }
+        }
+    }
+
+    /// Fill a rectangle with a colour index, clipped to this raster.
+    pub fn fill_rect(&mut self, r: Rect, c: u8) {
+        if r.x >= self.w || r.y >= self.h {
+            return;
+        }
+
+        let w = ::std::cmp::min(r.w, self.w - r.x);
+        let h = ::std::cmp::min(r.h, self.h - r.y);
+
+        for y in 0..h {
+            let start = self.stride * (self.y + r.y + y) + (self.x + r.x);
+            for e in &mut self.buf[start..(start + w)] {
+                *e = c;
+            }
+        }
+    }
+
+    /// Mirror the raster left to right.
+    pub fn flip_horizontal(&mut self) {
+        let start = self.stride * self.y;
+        let end = self.stride * (self.y + self.h);
+        for row in self.buf[start..end].chunks_mut(self.stride) {
+            row[self.x..(self.x + self.w)].reverse();
+        }
+    }
+
+    /// Mirror the raster top to bottom.
+    pub fn flip_vertical(&mut self) {
+        for y in 0..(self.h / 2) {
+            let top = self.stride * (self.y + y) + self.x;
+            let bottom = self.stride * (self.y + self.h - 1 - y) + self.x;
+            let (head, tail) = self.buf.split_at_mut(bottom);
+            head[top..(top + self.w)].swap_with_slice(&mut tail[..self.w]);
+        }
+    }
+}
+
+/// Check that a w x h region at (x, y) lies within a raster.
+fn check_sub_raster(
+        raster_w: usize, raster_h: usize,
+        x: usize, y: usize, w: usize, h: usize)
+        -> FlicResult<()> {
+    let x1 = x.checked_add(w).ok_or(FlicError::BadRaster("width overflow"))?;
+    let y1 = y.checked_add(h).ok_or(FlicError::BadRaster("height overflow"))?;
+    if x1 > raster_w || y1 > raster_h {
+        return Err(FlicError::BadRaster("sub-raster out of bounds"));
+    }
+
+    Ok(())
+}
+
+/// Check that a raster fits in its buffer and palette.
+fn check_raster(
+        x: usize, y: usize, w: usize, h: usize, stride: usize,
+        buf_len: usize, pal_len: usize)
+        -> FlicResult<()> {
+    if w <= 0 || h <= 0 {
+        return Err(FlicError::BadRaster("empty raster"));
+    }
+
+    let x1 = x.checked_add(w).ok_or(FlicError::BadRaster("width overflow"))?;
+    let y1 = y.checked_add(h).ok_or(FlicError::BadRaster("height overflow"))?;
+    if x1 > stride {
+        return Err(FlicError::BadRaster("raster wider than stride"));
+    }
+
+    let len = stride.checked_mul(y1).ok_or(FlicError::BadRaster("buffer size overflow"))?;
+    if len > buf_len {
+        return Err(FlicError::BadRaster("buffer too small"));
+    }
+
+    if pal_len != 3 * 256 {
+        return Err(FlicError::BadRaster("palette must have 256 entries"));
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use ::{Raster,RasterMut};
+    use ::{FlicError,Frame,Raster,RasterMut,Rect};
+
+    #[test]
+    fn test_frame() {
+        let buf = [
+            0, 0, 0, 0,
+            0, 1, 2, 0,
+            0, 3, 4, 0 ];
+        let mut pal = [0; 3 * 256];
+        pal[3] = 0xFF;
+
+        let raster = Raster::with_offset(1, 1, 2, 2, 4, &buf, &pal);
+        let mut a = Frame::from_raster(&raster);
+        assert_eq!((a.width(), a.height()), (2, 2));
+        assert_eq!(a.buf(), &[1, 2, 3, 4]);
+        assert_eq!(a.pal()[3], 0xFF);
+
+        let mut b = a.clone();
+        assert_eq!(a, b);
+
+        b.buf_mut()[0] = 5;
+        assert!(a != b);
+
+        a.swap(&mut b);
+        assert_eq!(a.buf()[0], 5);
+        assert_eq!(b.buf()[0], 1);
+    }
 
     #[test]
     #[should_panic]
@@ -123,4 +477,130 @@ mod tests {
         let _ = RasterMut::new(
                 ::std::usize::MAX, ::std::usize::MAX, &mut buf, &mut pal);
     }
+
+    #[test]
+    fn test_sub_raster() {
+        let buf = [
+            0, 1, 2, 3,
+            4, 5, 6, 7,
+            8, 9, 10, 11 ];
+        let pal = [0; 3 * 256];
+
+        let raster = Raster::with_offset(1, 0, 3, 3, 4, &buf, &pal);
+        let sub = raster.sub_raster(1, 1, 2, 2).expect("sub");
+        assert_eq!(sub.pixel(0, 0), Some(6));
+        assert_eq!(sub.pixel(1, 1), Some(11));
+        assert_eq!(sub.pixel(2, 0), None);
+
+        assert!(raster.sub_raster(2, 0, 2, 1).is_err());
+        assert!(raster.sub_raster(0, 0, 0, 1).is_err());
+    }
+
+    #[test]
+    fn test_blit_fill_flip() {
+        let mut buf = [0; 5 * 4];
+        let mut pal = [0; 3 * 256];
+        let src_buf = [
+            1, 2, 3,
+            0, 5, 0 ];
+        let src_pal = [0; 3 * 256];
+        let src = Raster::new(3, 2, &src_buf, &src_pal);
+
+        {
+            // A 4x3 view at (1, 1) of a 5x4 buffer.
+            let mut dst = RasterMut::with_offset(1, 1, 4, 3, 5, &mut buf, &mut pal);
+            dst.fill_rect(Rect { x: 0, y: 0, w: 100, h: 1 }, 9);
+            dst.blit_from(&src, 2, 1, Some(0));
+        }
+
+        assert_eq!(&buf[..], &[
+            0, 0, 0, 0, 0,
+            0, 9, 9, 9, 9,
+            0, 0, 0, 1, 2,
+            0, 0, 0, 0, 5 ]);
+
+        {
+            let mut dst = RasterMut::with_offset(1, 1, 4, 3, 5, &mut buf, &mut pal);
+            dst.flip_horizontal();
+        }
+
+        assert_eq!(&buf[..], &[
+            0, 0, 0, 0, 0,
+            0, 9, 9, 9, 9,
+            0, 2, 1, 0, 0,
+            0, 5, 0, 0, 0 ]);
+
+        {
+            let mut dst = RasterMut::with_offset(1, 1, 4, 3, 5, &mut buf, &mut pal);
+            dst.flip_vertical();
+
+            let mut sub = dst.sub_raster_mut(3, 0, 1, 3).expect("sub");
+            sub.fill_rect(Rect { x: 0, y: 0, w: 1, h: 3 }, 7);
+        }
+
+        assert_eq!(&buf[..], &[
+            0, 0, 0, 0, 0,
+            0, 5, 0, 0, 7,
+            0, 2, 1, 0, 7,
+            0, 9, 9, 9, 7 ]);
+    }
+
+    #[test]
+    fn test_blit_negative_offset() {
+        let mut buf = [0; 5 * 4];
+        let mut pal = [0; 3 * 256];
+        let src_buf = [
+            1, 2, 3,
+            4, 5, 6,
+            7, 8, 0 ];
+        let src_pal = [0; 3 * 256];
+        let src = Raster::new(3, 3, &src_buf, &src_pal);
+
+        {
+            // A 4x3 view at (1, 1) of a 5x4 buffer.
+            let mut dst = RasterMut::with_offset(1, 1, 4, 3, 5, &mut buf, &mut pal);
+            dst.blit_from(&src, -1, -2, None);
+            dst.blit_from(&src, 2, -1, Some(0));
+            dst.blit_from(&src, -3, 0, None);
+            dst.blit_from(&src, 0, -3, None);
+        }
+
+        assert_eq!(&buf[..], &[
+            0, 0, 0, 0, 0,
+            0, 8, 0, 4, 5,
+            0, 0, 0, 7, 8,
+            0, 0, 0, 0, 0 ]);
+    }
+
+    #[test]
+    fn test_raster_try_with_offset() {
+        let buf = [0; 16];
+        let pal = [0; 3 * 256];
+
+        assert!(Raster::try_with_offset(1, 1, 2, 3, 4, &buf, &pal).is_ok());
+
+        let check = |x, y, w, h, stride, reason| {
+            match Raster::try_with_offset(x, y, w, h, stride, &buf, &pal) {
+                Err(FlicError::BadRaster(r)) => assert_eq!(r, reason),
+                _ => panic!("expected {}", reason),
+            }
+        };
+
+        check(0, 0, 0, 4, 4, "empty raster");
+        check(1, 0, ::std::usize::MAX, 4, 4, "width overflow");
+        check(0, 1, 4, ::std::usize::MAX, 4, "height overflow");
+        check(1, 0, 4, 4, 4, "raster wider than stride");
+        check(0, 0, 4, 4, ::std::usize::MAX, "buffer size overflow");
+        check(0, 1, 4, 4, 4, "buffer too small");
+
+        match Raster::try_new(4, 4, &buf, &pal[..3]) {
+            Err(FlicError::BadRaster(r)) => assert_eq!(r, "palette must have 256 entries"),
+            _ => panic!(),
+        }
+
+        let mut buf = [0; 16];
+        let mut pal = [0; 3 * 256];
+        assert!(RasterMut::try_new(4, 4, &mut buf, &mut pal).is_ok());
+        assert!(RasterMut::try_new(5, 4, &mut buf, &mut pal).is_err());
+    }
 }
@@ -0,0 +1,226 @@
+//! FLIC encoder rate control.
+
+use ::Raster;
+use flic::SIZE_OF_FLIC_HEADER;
+
+/// Largest pixel tolerance the rate controller will use before it
+/// starts dropping frames.
+pub const MAX_RATE_TOLERANCE: u16 = 64;
+
+/// Size budget for rate-controlled encoding.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum RateBudget {
+    /// Total file size in bytes, for the given number of frames
+    /// (not including the ring frame).
+    FileSize(u64, u16),
+
+    /// Bytes per second of playback.
+    BytesPerSecond(u32),
+}
+
+/// Results of rate-controlled encoding.
+#[derive(Clone,Copy,Debug,Default)]
+pub struct RateControlReport {
+    /// Number of bytes written so far, including the header.
+    pub size: u64,
+
+    /// Number of frames written, including dropped frames.
+    pub frames: u16,
+
+    /// Number of frames that were dropped, i.e. written as empty
+    /// frames.
+    pub dropped_frames: u16,
+
+    /// Number of frames that were encoded as keyframes.
+    pub keyframes: u16,
+
+    /// Largest pixel tolerance that was used.
+    pub max_tolerance: u16,
+
+    /// Mean squared error of the decoded frames, per RGB channel.
+    pub mean_squared_error: f64,
+
+    /// Peak signal-to-noise ratio of the decoded frames, in dB.
+    /// Infinite if the animation was encoded losslessly.
+    pub psnr: f64,
+}
+
+/// Rate controller state.
+pub struct RateControl {
+    budget: RateBudget,
+    speed_msec: u32,
+    keyframe_interval: u16,
+    min_tolerance: u16,
+    tolerance: u16,
+    frames_since_keyframe: u16,
+    sum_squared_error: u64,
+    num_samples: u64,
+    report: RateControlReport,
+}
+
+impl RateControl {
+    /// Create a new rate controller.
+    ///
+    /// A keyframe interval of 0 disables keyframes after the first
+    /// frame.  The tolerance never drops below `min_tolerance`.
+    pub fn new(budget: RateBudget, speed_msec: u32,
+            keyframe_interval: u16, min_tolerance: u16)
+            -> Self {
+        RateControl {
+            budget: budget,
+            speed_msec: speed_msec,
+            keyframe_interval: keyframe_interval,
+            min_tolerance: min_tolerance,
+            tolerance: min_tolerance,
+            frames_since_keyframe: 0,
+            sum_squared_error: 0,
+            num_samples: 0,
+            report: RateControlReport {
+                max_tolerance: min_tolerance,
+                ..RateControlReport::default()
+            },
+        }
+    }
+
+    /// The target file size after writing the given frame.
+    pub fn target_size(&self, frame: u16)
+            -> u64 {
+        let n = frame as u64 + 1;
+        let hdr = SIZE_OF_FLIC_HEADER as u64;
+        match self.budget {
+            RateBudget::FileSize(size, frame_count) =>
+                hdr + size.saturating_sub(hdr) * n / (frame_count as u64 + 1),
+            RateBudget::BytesPerSecond(bps) =>
+                hdr + (bps as u64) * (self.speed_msec as u64) * n / 1000,
+        }
+    }
+
+    /// The pixel tolerance to try first for the next frame.
+    pub fn tolerance(&self) -> u16 {
+        self.tolerance
+    }
+
+    /// The next, larger pixel tolerance to try, if any.
+    pub fn next_tolerance(&self, tolerance: u16)
+            -> Option<u16> {
+        if tolerance >= MAX_RATE_TOLERANCE {
+            None
+        } else if tolerance <= 0 {
+            Some(1)
+        } else {
+            Some(::std::cmp::min(2 * tolerance, MAX_RATE_TOLERANCE))
+        }
+    }
+
+    /// Returns true if the next frame should be a keyframe.
+    pub fn want_keyframe(&self) -> bool {
+        self.keyframe_interval > 0
+            && self.frames_since_keyframe >= self.keyframe_interval
+    }
+
+    /// Record a written frame.
+    ///
+    /// `size` is the number of bytes written for the frame, and
+    /// `available` is the number of bytes that were available before
+    /// hitting the budget.
+    pub fn record_frame(&mut self,
+            size: usize, available: i64, tolerance: u16,
+            keyframe: bool, dropped: bool) {
+        self.report.frames = self.report.frames.saturating_add(1);
+
+        if dropped {
+            self.report.dropped_frames = self.report.dropped_frames + 1;
+        }
+
+        if keyframe {
+            self.report.keyframes = self.report.keyframes + 1;
+            self.frames_since_keyframe = 0;
+        } else {
+            self.frames_since_keyframe = self.frames_since_keyframe.saturating_add(1);
+        }
+
+        if tolerance > self.report.max_tolerance {
+            self.report.max_tolerance = tolerance;
+        }
+
+        // Relax the tolerance when there is plenty of room.
+        if !dropped {
+            self.tolerance = tolerance;
+            if (size as i64) * 2 < available {
+                self.tolerance = ::std::cmp::max(self.min_tolerance, tolerance / 2);
+            }
+        }
+    }
+
+    /// Accumulate the error between the source frame and the frame
+    /// as it will be decoded.
+    pub fn record_error(&mut self, src: &Raster, buf: &[u8], pal: &[u8]) {
+        let start = src.stride * src.y;
+        let end = src.stride * (src.y + src.h);
+        for (src_row, row) in src.buf[start..end].chunks(src.stride)
+                .zip(buf.chunks(src.w)) {
+            for (&a, &b) in src_row[src.x..(src.x + src.w)].iter().zip(row.iter()) {
+                let a = 3 * a as usize;
+                let b = 3 * b as usize;
+                for i in 0..3 {
+                    let d = (src.pal[a + i] as i64) - (pal[b + i] as i64);
+                    self.sum_squared_error = self.sum_squared_error + (d * d) as u64;
+                }
+            }
+        }
+
+        self.num_samples = self.num_samples + 3 * (src.w * src.h) as u64;
+    }
+
+    /// Produce a report, given the number of bytes written so far.
+    pub fn report(&self, size: u64)
+            -> RateControlReport {
+        let mse = if self.num_samples > 0 {
+            self.sum_squared_error as f64 / self.num_samples as f64
+        } else {
+            0.0
+        };
+
+        let psnr = if mse > 0.0 {
+            10.0 * (255.0 * 255.0 / mse).log10()
+        } else {
+            ::std::f64::INFINITY
+        };
+
+        RateControlReport {
+            size: size,
+            mean_squared_error: mse,
+            psnr: psnr,
+            ..self.report
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RateBudget,RateControl,MAX_RATE_TOLERANCE};
+
+    #[test]
+    fn test_target_size() {
+        let rate = RateControl::new(RateBudget::FileSize(128 + 1000, 9), 70, 0, 0);
+        assert_eq!(rate.target_size(0), 128 + 100);
+        assert_eq!(rate.target_size(9), 128 + 1000);
+
+        let rate = RateControl::new(RateBudget::BytesPerSecond(1000), 100, 0, 0);
+        assert_eq!(rate.target_size(0), 128 + 100);
+        assert_eq!(rate.target_size(9), 128 + 1000);
+    }
+
+    #[test]
+    fn test_next_tolerance() {
+        let rate = RateControl::new(RateBudget::BytesPerSecond(1000), 100, 0, 0);
+        let mut tolerances = Vec::new();
+        let mut tol = Some(0);
+        while let Some(t) = tol {
+            tolerances.push(t);
+            tol = rate.next_tolerance(t);
+        }
+
+        assert_eq!(&tolerances[..], &[ 0, 1, 2, 4, 8, 16, 32, MAX_RATE_TOLERANCE ]);
+    }
+}
@@ -0,0 +1,173 @@
+//! Palette-applied truecolor output.
+//!
+//! Each conversion builds a 256-entry lookup table of packed pixels
+//! from the palette, so the row loops are a table lookup and a copy
+//! per pixel.
+
+use ::{FlicError,FlicResult,Raster};
+
+/// Layout of a rendered pixel.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum RenderFormat {
+    /// Three bytes per pixel: red, green, blue.
+    Rgb24,
+
+    /// Four bytes per pixel: red, green, blue, alpha.
+    Rgba32,
+
+    /// Four bytes per pixel: blue, green, red, alpha.
+    Bgra32,
+
+    /// Two bytes per pixel, little endian, with 5 bits of red, 6 bits
+    /// of green, and 5 bits of blue.
+    Rgb565,
+}
+
+impl RenderFormat {
+    /// The number of bytes per pixel.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match *self {
+            RenderFormat::Rgb24 => 3,
+            RenderFormat::Rgba32 | RenderFormat::Bgra32 => 4,
+            RenderFormat::Rgb565 => 2,
+        }
+    }
+}
+
+/// Render the raster into a truecolor buffer, applying the palette.
+///
+/// `pitch` is the number of bytes between rows of the destination.
+/// For formats with alpha, pixels of the transparent colour index, if
+/// any, get alpha 0 and all other pixels get alpha 255.
+///
+/// # Examples
+///
+/// ```
+/// use flic::render::{RenderFormat,render};
+///
+/// const SCREEN_W: usize = 320;
+/// const SCREEN_H: usize = 200;
+/// let buf = [0; SCREEN_W * SCREEN_H];
+/// let pal = [0; 3 * 256];
+/// let raster = flic::Raster::new(SCREEN_W, SCREEN_H, &buf, &pal);
+/// let mut rgba = vec![0; 4 * SCREEN_W * SCREEN_H];
+///
+/// render(&raster, RenderFormat::Rgba32, None, &mut rgba, 4 * SCREEN_W)
+///     .expect("render");
+/// ```
+pub fn render(
+        src: &Raster, format: RenderFormat, transparent: Option<u8>,
+        dst: &mut [u8], pitch: usize)
+        -> FlicResult<()> {
+    let bpp = format.bytes_per_pixel();
+    let row_len = src.w.checked_mul(bpp).ok_or(FlicError::ExceededLimit)?;
+    let len = pitch.checked_mul(src.h - 1)
+        .and_then(|n| n.checked_add(row_len))
+        .ok_or(FlicError::ExceededLimit)?;
+
+    if pitch < row_len || dst.len() < len {
+        return Err(FlicError::BadInput);
+    }
+
+    match format {
+        RenderFormat::Rgb24 => {
+            let mut lut = [[0; 3]; 256];
+            for (c, e) in lut.iter_mut().enumerate() {
+                e.copy_from_slice(&src.pal[(3 * c)..(3 * c + 3)]);
+            }
+            render_lut(src, &lut, dst, pitch);
+        },
+        RenderFormat::Rgba32 | RenderFormat::Bgra32 => {
+            let mut lut = [[0; 4]; 256];
+            for (c, e) in lut.iter_mut().enumerate() {
+                let r = src.pal[3 * c + 0];
+                let g = src.pal[3 * c + 1];
+                let b = src.pal[3 * c + 2];
+                let a = if transparent == Some(c as u8) { 0x00 } else { 0xFF };
+                *e = if format == RenderFormat::Rgba32 { [r, g, b, a] } else { [b, g, r, a] };
+            }
+            render_lut(src, &lut, dst, pitch);
+        },
+        RenderFormat::Rgb565 => {
+            let mut lut = [[0; 2]; 256];
+            for (c, e) in lut.iter_mut().enumerate() {
+                let r = (src.pal[3 * c + 0] >> 3) as u16;
+                let g = (src.pal[3 * c + 1] >> 2) as u16;
+                let b = (src.pal[3 * c + 2] >> 3) as u16;
+                let p = (r << 11) | (g << 5) | b;
+                *e = [p as u8, (p >> 8) as u8];
+            }
+            render_lut(src, &lut, dst, pitch);
+        },
+    }
+
+    Ok(())
+}
+
+/// Copy each pixel's lookup table entry into the destination.
+fn render_lut<T: AsRef<[u8]>>(
+        src: &Raster, lut: &[T; 256], dst: &mut [u8], pitch: usize) {
+    let bpp = lut[0].as_ref().len();
+    let src_start = src.stride * src.y;
+    let src_end = src.stride * (src.y + src.h);
+    let src_rows = src.buf[src_start..src_end].chunks(src.stride);
+
+    for (src_row, dst_row) in src_rows.zip(dst.chunks_mut(pitch)) {
+        let src_row = &src_row[src.x..(src.x + src.w)];
+        let dst_row = &mut dst_row[..(bpp * src.w)];
+
+        for (&c, d) in src_row.iter().zip(dst_row.chunks_exact_mut(bpp)) {
+            d.copy_from_slice(lut[c as usize].as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::Raster;
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let buf = [
+            9, 9, 9, 9,
+            9, 0, 1, 9 ];
+        let mut pal = [0; 3 * 256];
+        pal[0..3].copy_from_slice(&[0x10, 0x20, 0x30]);
+        pal[3..6].copy_from_slice(&[0xFF, 0x80, 0x08]);
+
+        let raster = Raster::with_offset(1, 1, 2, 1, 4, &buf, &pal);
+
+        let mut dst = [0xAA; 7];
+        render(&raster, RenderFormat::Rgb24, None, &mut dst, 7).expect("rgb24");
+        assert_eq!(dst, [0x10, 0x20, 0x30, 0xFF, 0x80, 0x08, 0xAA]);
+
+        let mut dst = [0; 8];
+        render(&raster, RenderFormat::Rgba32, Some(1), &mut dst, 8).expect("rgba32");
+        assert_eq!(dst, [0x10, 0x20, 0x30, 0xFF, 0xFF, 0x80, 0x08, 0x00]);
+
+        render(&raster, RenderFormat::Bgra32, None, &mut dst, 8).expect("bgra32");
+        assert_eq!(dst, [0x30, 0x20, 0x10, 0xFF, 0x08, 0x80, 0xFF, 0xFF]);
+
+        let mut dst = [0; 4];
+        render(&raster, RenderFormat::Rgb565, None, &mut dst, 4).expect("rgb565");
+        assert_eq!(dst, [0x06, 0x11, 0x01, 0xFC]);
+
+        // Destination too small.
+        let mut dst = [0; 7];
+        assert!(render(&raster, RenderFormat::Rgba32, None, &mut dst, 8).is_err());
+    }
+
+    #[test]
+    fn test_render_pitch() {
+        let buf = [ 0, 1, 1, 0 ];
+        let mut pal = [0; 3 * 256];
+        pal[3..6].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+
+        let raster = Raster::new(2, 2, &buf, &pal);
+        let mut dst = [0x55; 8 + 6];
+        render(&raster, RenderFormat::Rgb24, None, &mut dst, 8).expect("rgb24");
+        assert_eq!(&dst[0..8], &[0, 0, 0, 0xFF, 0xFF, 0xFF, 0x55, 0x55]);
+        assert_eq!(&dst[8..14], &[0xFF, 0xFF, 0xFF, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,174 @@
+//! Variable frame timing.
+//!
+//! A FLIC plays every frame for the same amount of time, so frames
+//! with longer durations are approximated by holding them for several
+//! ticks, i.e. following them with empty frames.
+
+/// Results of timing frames with per-frame durations.
+#[derive(Clone,Copy,Debug,Default)]
+pub struct TimingReport {
+    /// Number of timed frames written, not including holds.
+    pub frames: u32,
+
+    /// Number of ticks the timed frames were held for, including the
+    /// frames themselves.
+    pub ticks: u32,
+
+    /// Total of the requested durations, in milliseconds.
+    pub target_msec: f64,
+
+    /// Total playback time of the timed frames, in milliseconds.
+    pub actual_msec: f64,
+
+    /// Difference between the actual and requested playback time at
+    /// the end of the last frame, in milliseconds.
+    pub total_error_msec: f64,
+
+    /// Largest difference between the actual and requested time at
+    /// the end of any frame, in milliseconds.
+    pub max_error_msec: f64,
+}
+
+/// Converts per-frame durations into a number of ticks, carrying the
+/// rounding error over to the following frames.
+#[derive(Clone,Copy,Debug)]
+pub struct FrameTiming {
+    tick_usec: u64,
+    target_usec: u64,
+    actual_usec: u64,
+    frames: u32,
+    ticks: u32,
+    max_error_usec: u64,
+}
+
+impl FrameTiming {
+    /// Create a frame timer for the given tick length, in
+    /// microseconds.
+    pub fn new(tick_usec: u64) -> Self {
+        FrameTiming {
+            tick_usec: ::std::cmp::max(tick_usec, 1),
+            target_usec: 0,
+            actual_usec: 0,
+            frames: 0,
+            ticks: 0,
+            max_error_usec: 0,
+        }
+    }
+
+    /// The number of ticks to hold the next frame for.
+    ///
+    /// Every frame is shown for at least one tick.
+    pub fn next_ticks(&mut self, duration_msec: u32)
+            -> u32 {
+        self.target_usec = self.target_usec + 1000 * duration_msec as u64;
+
+        let remaining = self.target_usec.saturating_sub(self.actual_usec);
+        let ticks = ::std::cmp::max(1, (remaining + self.tick_usec / 2) / self.tick_usec);
+        let ticks = ::std::cmp::min(ticks, ::std::u32::MAX as u64) as u32;
+
+        self.actual_usec = self.actual_usec + ticks as u64 * self.tick_usec;
+        self.frames = self.frames + 1;
+        self.ticks = self.ticks.saturating_add(ticks);
+
+        let error = self.actual_usec.abs_diff(self.target_usec);
+        if error > self.max_error_usec {
+            self.max_error_usec = error;
+        }
+
+        ticks
+    }
+
+    /// Produce a report of the timing so far.
+    pub fn report(&self)
+            -> TimingReport {
+        TimingReport {
+            frames: self.frames,
+            ticks: self.ticks,
+            target_msec: self.target_usec as f64 / 1000.0,
+            actual_msec: self.actual_usec as f64 / 1000.0,
+            total_error_msec: self.actual_usec.abs_diff(self.target_usec) as f64 / 1000.0,
+            max_error_msec: self.max_error_usec as f64 / 1000.0,
+        }
+    }
+}
+
+/// Choose a base tick for the given frame durations, in milliseconds.
+///
+/// The tick is no longer than the shortest duration.  Among the
+/// ticks that keep the animation within `max_ticks` ticks, the one
+/// with the smallest timing error is chosen, preferring longer ticks
+/// since they need fewer empty frames.
+///
+/// # Examples
+///
+/// ```
+/// use flic::timing::choose_speed_msec;
+///
+/// // A title card held for 2 seconds, followed by 100 ms frames.
+/// let durations = [ 2000, 100, 100, 100, 150 ];
+/// assert_eq!(choose_speed_msec(&durations, 1000), 50);
+/// ```
+pub fn choose_speed_msec(durations_msec: &[u32], max_ticks: u32)
+        -> u32 {
+    let shortest = durations_msec.iter().cloned().min().unwrap_or(1);
+    let mut best_speed = ::std::cmp::max(shortest, 1);
+    let mut best_error = ::std::u64::MAX;
+
+    for speed in (1..(best_speed + 1)).rev() {
+        let mut timing = FrameTiming::new(1000 * speed as u64);
+        for &d in durations_msec {
+            timing.next_ticks(d);
+        }
+
+        if timing.ticks > max_ticks {
+            continue;
+        }
+
+        let error = timing.max_error_usec;
+        if error < best_error {
+            best_speed = speed;
+            best_error = error;
+            if error == 0 {
+                break;
+            }
+        }
+    }
+
+    best_speed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FrameTiming,choose_speed_msec};
+
+    #[test]
+    fn test_next_ticks() {
+        let mut timing = FrameTiming::new(40000);
+        let ticks: Vec<u32> = [ 100, 100, 100, 10, 1000 ].iter()
+            .map(|&d| timing.next_ticks(d))
+            .collect();
+
+        // 100 ms is 2.5 ticks; the error is carried forward.  The
+        // 10 ms frame still needs a whole tick.
+        assert_eq!(&ticks[..], &[ 3, 2, 3, 1, 24 ]);
+
+        let report = timing.report();
+        assert_eq!(report.frames, 5);
+        assert_eq!(report.ticks, 33);
+        assert_eq!(report.target_msec, 1310.0);
+        assert_eq!(report.actual_msec, 1320.0);
+        assert_eq!(report.total_error_msec, 10.0);
+        assert_eq!(report.max_error_msec, 50.0);
+    }
+
+    #[test]
+    fn test_choose_speed_msec() {
+        assert_eq!(choose_speed_msec(&[ 100, 100, 100 ], 1000), 100);
+        assert_eq!(choose_speed_msec(&[ 100, 250 ], 1000), 50);
+        assert_eq!(choose_speed_msec(&[ 70, 105 ], 1000), 35);
+
+        // Limited number of ticks.
+        assert_eq!(choose_speed_msec(&[ 100, 250 ], 4), 90);
+        assert_eq!(choose_speed_msec(&[], 1000), 1);
+    }
+}